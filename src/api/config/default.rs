@@ -1,4 +1,6 @@
 pub const DEFAULT_CONFIG: &str = r#"
+# "runner" completes the task below, "assistant" starts a conversation.
+type: runner
 personality: A superintelligent AI.
 task: Write an essay on the Rust programming language.
 agents:
@@ -46,4 +48,53 @@ plugins:
     brainstorm: {}
     #file system: {}
 disabled tools: []
+# "steps" runs one tool per request, "script" writes one script per subtask.
+static mode: steps
+# "ask" confirms actions that need permission, "allow all" runs every action, "deny all" runs none.
+approval:
+    mode: ask
+    allow: []
+    deny: []
+# Agents only recall memories from the same namespace.
+#namespace: my-project
+# Runs are saved here after every step, and can be picked up again with `--resume <run id>`.
+checkpoints: checkpoints
+# Ends the run early once any of these run out. Remove a limit to leave it unbounded.
+budget:
+    max decisions: 20
+    max steps: 10
+    max tokens: 500000
+    # In dollars, estimated from OpenAI's prices.
+    max cost: 2.00
+    # In seconds.
+    max time: 3600
+# Dollars per 1,000 tokens, for models without a built-in price.
+prices: {}
+#    my-model:
+#        prompt: 0.001
+#        completion: 0.002
+# Failed requests that are worth trying again, like rate limits and server errors.
+retry:
+    max retries: 3
+    # In seconds, doubled after every retry.
+    initial delay: 1
+    max delay: 60
+    # In seconds, for each attempt.
+    timeout: 300
+# Limits on the requests to each provider, shared by every agent.
+rate limits: {}
+#    chatgpt:
+#        concurrency: 2
+#        requests per minute: 60
+# Keeps responses and embeddings on disk, to skip identical requests when a task is run again.
+cache: {}
+#    responses:
+#        directory: cache/responses
+#        # In seconds.
+#        ttl: 86400
+#        # In megabytes.
+#        max size: 100
+#    embeddings:
+#        directory: cache/embeddings
+#        max size: 100
 "#;
\ No newline at end of file
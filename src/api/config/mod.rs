@@ -1,10 +1,10 @@
-use std::{collections::HashMap, error::Error, fmt::Display, process, sync::{Mutex, Arc}};
+use std::{collections::HashMap, error::Error, fmt::Display, process, sync::{Mutex, Arc}, path::PathBuf};
 
 use colored::Colorize;
 use serde::{Serialize, Deserialize};
-use serde_json::Value;
+use serde_json::{Value, json};
 
-use crate::{CommandContext, LLM, Plugin, create_browse, create_google, create_filesystem, create_wolfram, create_news, LLMProvider, create_model_chatgpt, Agents, LLMModel, create_model_llama, AgentInfo, MemoryProvider, create_memory_local, create_memory_qdrant, MemorySystem, create_memory_redis, PluginStore, create_brainstorm, SmartGPT};
+use crate::{CommandContext, LLM, Plugin, create_browse, create_google, create_filesystem, create_wolfram, create_news, LLMProvider, create_model_chatgpt, create_model_anthropic, create_model_compatible, create_model_ollama, create_model_mock, EmbeddingProvider, EmbeddingModel, create_embedding_openai, create_embedding_hashed, Agents, LLMModel, create_model_llama, AgentInfo, MemoryProvider, MemoryMetadata, create_memory_local, create_memory_qdrant, MemorySystem, create_memory_redis, create_memory_file, PluginStore, create_brainstorm, SmartGPT, StaticMode, ApprovalPolicy, RunState, Progress, create_run_id, Budget, BudgetLimits, RunUsage, MeteredModel, ModelPrice, get_model_price, get_default_prices, RetryModel, RetryPolicy, RateLimit, RateLimiter, get_rate_limiter, FallbackModel, CachedModel, CachedEmbeddings, CacheConfig, DiskCache};
 
 mod default;
 pub use default::*;
@@ -31,11 +31,46 @@ impl<'a> Display for NoMemorySystemError {
 
 impl<'a> Error for NoMemorySystemError {}
 
+#[derive(Debug, Clone)]
+pub struct NoEmbeddingProviderError(pub String);
+
+impl Display for NoEmbeddingProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no embedding provider named '{}'", self.0)
+    }
+}
+
+impl Error for NoEmbeddingProviderError {}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AgentConfig {
-    pub llm: HashMap<String, Value>,
-    pub memory: HashMap<String, Value>
+    pub llm: LLMConfig,
+    pub memory: HashMap<String, Value>,
+    /// Embeds memories with a different provider than `llm`.
+    #[serde(default)] pub embedding: Option<HashMap<String, Value>>,
+    /// Where reflections are kept. They're kept apart from `memory` in the same provider without one.
+    #[serde(default)] pub reflections: Option<HashMap<String, Value>>,
+    /// How many observations are reflected on at once. `null` turns reflection off.
+    #[serde(rename = "reflect every", default = "default_reflect_every")] pub reflect_every: Option<usize>
+}
+
+fn default_reflect_every() -> Option<usize> {
+    Some(10)
+}
+
+/// One model, or a list of models to fall back on in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LLMConfig {
+    Single(HashMap<String, Value>),
+    Chain(Vec<HashMap<String, Value>>)
+}
+
+impl Default for LLMConfig {
+    fn default() -> Self {
+        Self::Single(HashMap::new())
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -50,11 +85,36 @@ pub struct AgentLLMs {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
+    #[serde(rename = "type", default)] pub auto_type: AutoType,
     pub task: String,
     pub personality: String,
     pub agents: AgentLLMs,
     pub plugins: HashMap<String, Value>,
-    #[serde(rename = "disabled tools")] pub disabled_tools: Vec<String>
+    #[serde(rename = "disabled tools")] pub disabled_tools: Vec<String>,
+    #[serde(rename = "static mode", default)] pub static_mode: StaticMode,
+    #[serde(default)] pub approval: ApprovalPolicy,
+    /// The directory runs are checkpointed to. Runs aren't checkpointed without one.
+    #[serde(default)] pub checkpoints: Option<String>,
+    #[serde(default)] pub budget: BudgetLimits,
+    /// Dollars per 1,000 tokens for each model, on top of the built-in OpenAI prices.
+    #[serde(default)] pub prices: HashMap<String, ModelPrice>,
+    #[serde(default)] pub retry: RetryPolicy,
+    /// Limits on the requests to each provider, by provider name.
+    #[serde(rename = "rate limits", default)] pub rate_limits: HashMap<String, RateLimit>,
+    #[serde(default)] pub cache: CacheConfig,
+    /// Tags every memory, so runs in different namespaces don't recall each other's memories.
+    #[serde(default)] pub namespace: Option<String>
+}
+
+/// What every agent's model is built with, besides its own config.
+pub struct ModelOptions {
+    pub prices: HashMap<String, ModelPrice>,
+    pub usage: Arc<Mutex<RunUsage>>,
+    pub retry: RetryPolicy,
+    /// Shared by every agent using the same provider.
+    pub rate_limiters: HashMap<String, Arc<RateLimiter>>,
+    pub response_cache: Option<Arc<DiskCache>>,
+    pub embedding_cache: Option<Arc<DiskCache>>
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -66,12 +126,12 @@ pub struct Llm {
     pub openai_key: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum AutoType {
-    #[serde(rename = "runner")] Runner {
-        task: String
-    }
+    #[default]
+    #[serde(rename = "runner")] Runner,
+    #[serde(rename = "assistant")] Assistant
 }
 
 pub fn list_plugins() -> Vec<Plugin> {
@@ -88,7 +148,11 @@ pub fn list_plugins() -> Vec<Plugin> {
 pub fn create_llm_providers() -> Vec<Box<dyn LLMProvider>> {
     vec![
         create_model_chatgpt(),
-        create_model_llama()
+        create_model_llama(),
+        create_model_anthropic(),
+        create_model_compatible(),
+        create_model_ollama(),
+        create_model_mock()
     ]
 }
 
@@ -96,11 +160,37 @@ pub fn create_memory_providers() -> Vec<Box<dyn MemoryProvider>> {
     vec![
         create_memory_local(),
         create_memory_qdrant(),
-        create_memory_redis()
+        create_memory_redis(),
+        create_memory_file()
     ]
 }
 
-fn create_llm_model(agent: HashMap<String, Value>) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
+fn create_llm_model(
+    role: &str,
+    agent: LLMConfig,
+    options: &ModelOptions
+) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
+    match agent {
+        LLMConfig::Single(model) => create_single_llm_model(role, model, options),
+        LLMConfig::Chain(models) => {
+            let mut models = models.into_iter()
+                .map(|model| create_single_llm_model(role, model, options))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match models.len() {
+                0 => Err(Box::new(NoLLMError)),
+                1 => Ok(models.remove(0)),
+                _ => Ok(Box::new(FallbackModel::new(models)))
+            }
+        }
+    }
+}
+
+fn create_single_llm_model(
+    role: &str,
+    agent: HashMap<String, Value>,
+    options: &ModelOptions
+) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
     let (model_name, model_config) = agent.iter().next().ok_or(NoLLMError)?;
     let providers = create_llm_providers();
     let llm_provider = providers.iter()
@@ -108,10 +198,78 @@ fn create_llm_model(agent: HashMap<String, Value>) -> Result<Box<dyn LLMModel>,
         .find(|el| el.get_name().to_ascii_lowercase() == model_name.to_ascii_lowercase())
         .ok_or(NoLLMError)?;
 
-    Ok(llm_provider.create(model_config.clone())?)
+    let model = model_config.get("model")
+        .and_then(|el| el.as_str())
+        .unwrap_or(model_name);
+
+    let retry_model = RetryModel {
+        model: llm_provider.create(model_config.clone())?,
+        policy: options.retry.clone(),
+        limiter: options.rate_limiters.get(&llm_provider.get_name().to_ascii_lowercase()).cloned()
+    };
+
+    let metered_model = MeteredModel {
+        model: Box::new(retry_model),
+        agent: role.to_string(),
+        model_name: model.to_string(),
+        price: get_model_price(&options.prices, model),
+        usage: options.usage.clone()
+    };
+
+    if options.response_cache.is_none() && options.embedding_cache.is_none() {
+        return Ok(Box::new(metered_model));
+    }
+
+    Ok(Box::new(CachedModel {
+        model: Box::new(metered_model),
+        identity: get_cache_identity(model_name, model_config),
+        responses: options.response_cache.clone(),
+        embeddings: options.embedding_cache.clone(),
+        last_key: Mutex::new(None)
+    }))
+}
+
+/// Identifies a model by its provider and config, leaving out keys so they can be changed
+/// without losing the cache.
+fn get_cache_identity(provider: &str, config: &Value) -> Value {
+    let mut config = config.clone();
+    if let Value::Object(config) = &mut config {
+        config.retain(|key, _| !key.to_ascii_lowercase().contains("key"));
+    }
+
+    json!({ provider.to_ascii_lowercase(): config })
 }
 
-fn create_memory_model(agent: HashMap<String, Value>) -> Result<Box<dyn MemorySystem>, Box<dyn Error>> {
+pub fn create_embedding_providers() -> Vec<Box<dyn EmbeddingProvider>> {
+    vec![
+        create_embedding_openai(),
+        create_embedding_hashed()
+    ]
+}
+
+pub fn create_embedding_model(embedding: HashMap<String, Value>, cache: Option<Arc<DiskCache>>) -> Result<Box<dyn EmbeddingModel>, Box<dyn Error>> {
+    let (provider_name, provider_config) = embedding.into_iter().next()
+        .ok_or(NoEmbeddingProviderError("".to_string()))?;
+    let providers = create_embedding_providers();
+    let embedding_provider = providers.iter()
+        .filter(|el| el.is_enabled())
+        .find(|el| el.get_name().eq_ignore_ascii_case(&provider_name))
+        .ok_or(NoEmbeddingProviderError(provider_name.clone()))?;
+
+    let identity = get_cache_identity(&provider_name, &provider_config);
+    let model = embedding_provider.create(provider_config)?;
+
+    // Hashed embeddings are free to make, and caching them would keep ones from an older hash.
+    let cache = cache.filter(|_| !provider_name.eq_ignore_ascii_case("hashed"));
+
+    Ok(match cache {
+        Some(cache) => Box::new(CachedEmbeddings { model, identity, cache }),
+        None => model
+    })
+}
+
+/// Creates a memory system, in `namespace` of the provider if one is given.
+fn create_memory_model(agent: HashMap<String, Value>, namespace: Option<&str>) -> Result<Box<dyn MemorySystem>, Box<dyn Error>> {
     let (model_name, model_config) = agent.iter().next().ok_or(NoLLMError)?;
     let providers = create_memory_providers();
     let memory_provider = providers.iter()
@@ -119,19 +277,36 @@ fn create_memory_model(agent: HashMap<String, Value>) -> Result<Box<dyn MemorySy
         .find(|el| el.get_name().to_ascii_lowercase() == model_name.to_ascii_lowercase())
         .ok_or(NoMemorySystemError)?;
 
-    Ok(memory_provider.create(model_config.clone())?)
+    let model_config = match namespace {
+        Some(namespace) => memory_provider.get_namespaced_config(model_config, namespace),
+        None => model_config.clone()
+    };
+
+    Ok(memory_provider.create(model_config)?)
 }
 
-pub fn create_agent(agent: AgentConfig) -> Result<AgentInfo, Box<dyn Error>> {
+pub fn create_agent(role: &str, agent: AgentConfig, options: &ModelOptions, namespace: Option<String>) -> Result<AgentInfo, Box<dyn Error>> {
     Ok(AgentInfo {
         llm: LLM {
             prompt: vec![],
             message_history: vec![],
             end_prompt: vec![],
-            model: create_llm_model(agent.llm)?
+            model: create_llm_model(role, agent.llm, options)?,
+            embedding: agent.embedding
+                .map(|embedding| create_embedding_model(embedding, options.embedding_cache.clone()))
+                .transpose()?
+        },
+        reflections: match agent.reflections {
+            Some(reflections) => create_memory_model(reflections, None)?,
+            None => create_memory_model(agent.memory.clone(), Some("reflections"))?
         },
-        observations: create_memory_model(agent.memory.clone())?,
-        reflections: create_memory_model(agent.memory)?
+        observations: create_memory_model(agent.memory, None)?,
+        reflect_every: agent.reflect_every,
+        unreflected: vec![],
+        memory_metadata: MemoryMetadata {
+            namespace,
+            ..Default::default()
+        }
     })
 }
 
@@ -143,7 +318,7 @@ pub fn load_config(config: &str) -> Result<(String, SmartGPT), Box<dyn Error>> {
     for (name, _) in &config.plugins {
         let plugin = plugins.iter().find(|el| el.name.to_ascii_lowercase() == name.to_ascii_lowercase());
         if let None = plugin {
-            println!("{}: No plugin named \"{}\".", "Error".red(), name);
+            eprintln!("{}: No plugin named \"{}\".", "Error".red(), name);
             exit = true;
         }
     }
@@ -151,17 +326,43 @@ pub fn load_config(config: &str) -> Result<(String, SmartGPT), Box<dyn Error>> {
         process::exit(1);
     }
 
+    let budget = Budget::new(config.budget);
+
+    let mut prices = get_default_prices();
+    prices.extend(config.prices);
+
+    let options = ModelOptions {
+        prices,
+        usage: budget.usage.clone(),
+        retry: config.retry,
+        rate_limiters: config.rate_limits.iter()
+            .map(|(provider, limit)| (provider.to_ascii_lowercase(), get_rate_limiter(provider, limit)))
+            .collect(),
+        response_cache: config.cache.responses.as_ref().map(DiskCache::new).transpose()?.map(Arc::new),
+        embedding_cache: config.cache.embeddings.as_ref().map(DiskCache::new).transpose()?.map(Arc::new)
+    };
+
     let mut context = CommandContext {
         assets: HashMap::new(),
         plugin_data: PluginStore::new(),
         plugins: vec![],
         disabled_tools: config.disabled_tools,
+        static_mode: config.static_mode,
+        approval: config.approval,
+        run: RunState {
+            id: create_run_id(),
+            task: config.task.clone(),
+            checkpoint_dir: config.checkpoints.map(PathBuf::from),
+            progress: Progress::default(),
+            started: false
+        },
         agents: Agents {
-            static_agent: create_agent(config.agents.static_agent)?,
-            planner: create_agent(config.agents.planner)?,
-            dynamic: create_agent(config.agents.dynamic)?,
-            fast: create_agent(config.agents.fast)?
-        }
+            static_agent: create_agent("static", config.agents.static_agent, &options, config.namespace.clone())?,
+            planner: create_agent("planner", config.agents.planner, &options, config.namespace.clone())?,
+            dynamic: create_agent("dynamic", config.agents.dynamic, &options, config.namespace.clone())?,
+            fast: create_agent("fast", config.agents.fast, &options, config.namespace.clone())?
+        },
+        budget
     };
     
     for plugin in plugins {
@@ -179,6 +380,8 @@ pub fn load_config(config: &str) -> Result<(String, SmartGPT), Box<dyn Error>> {
         config.task,
         SmartGPT {
             personality: config.personality,
+            auto_type: config.auto_type,
+            conversation: vec![],
             context: Arc::new(Mutex::new(context))
         }
     ))
@@ -0,0 +1,229 @@
+use std::{error::Error, fmt::Display, fs::File, io::{self, BufRead, BufReader, BufWriter, Write}};
+
+use colored::Colorize;
+
+use crate::{CommandContext, AgentInfo, Memory, MemoryFilter, MemorySystem, Weights, score_memories, get_timestamp};
+
+#[derive(Debug, Clone)]
+pub struct MemoryCommandError(pub String);
+
+impl Display for MemoryCommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for MemoryCommandError {}
+
+pub const MEMORY_USAGE: &str = "Usage: smartgpt memory <command> [options]
+
+Commands:
+    list                    List memories.
+    search <query>          Search memories, with their scores. --count sets how many (10 by default).
+    export <file>           Write memories to a JSONL file, with their embeddings. `-` writes to stdout.
+    import <file>           Add memories from a JSONL file written by `export`.
+    delete                  Delete memories by --id, which can be given more than once, or by filter.
+    prune                   Delete memories older than --older-than days, or recalled fewer than --min-recall times.
+
+Options:
+    --agent <name>          static, planner, dynamic or fast (static by default).
+    --reflections           Use the agent's reflections instead of its observations.
+    --namespace <name>      Only memories from this namespace (the config's namespace by default).
+    --run <id>, --task <task>, --tool <tool>, --url <url>
+                            Only memories from this run, task, tool or URL.
+    --after <ms>, --before <ms>
+                            Only memories made in this range, in milliseconds since the Unix epoch.";
+
+const FILTER_FLAGS: [&str; 7] = [ "--namespace", "--run", "--task", "--tool", "--url", "--after", "--before" ];
+
+fn get_flag(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|ind| args.get(ind + 1))
+        .cloned()
+}
+
+fn get_flags(args: &[String], flag: &str) -> Vec<String> {
+    args.iter()
+        .enumerate()
+        .filter(|(_, arg)| *arg == flag)
+        .flat_map(|(ind, _)| args.get(ind + 1))
+        .cloned()
+        .collect()
+}
+
+fn parse_flag<T: std::str::FromStr>(args: &[String], flag: &str) -> Result<Option<T>, MemoryCommandError> {
+    get_flag(args, flag)
+        .map(|value| value.parse().map_err(|_| MemoryCommandError(format!("'{value}' isn't a valid value for {flag}"))))
+        .transpose()
+}
+
+fn get_filter(args: &[String], agent: &AgentInfo) -> Result<MemoryFilter, MemoryCommandError> {
+    Ok(MemoryFilter {
+        namespace: get_flag(args, "--namespace").or(agent.memory_metadata.namespace.clone()),
+        run: get_flag(args, "--run"),
+        task: get_flag(args, "--task"),
+        tool: get_flag(args, "--tool"),
+        url: get_flag(args, "--url"),
+        after: parse_flag(args, "--after")?,
+        before: parse_flag(args, "--before")?
+    })
+}
+
+fn get_agent<'a>(context: &'a mut CommandContext, args: &[String]) -> Result<&'a mut AgentInfo, MemoryCommandError> {
+    let agents = &mut context.agents;
+    match get_flag(args, "--agent").as_deref() {
+        None | Some("static") => Ok(&mut agents.static_agent),
+        Some("planner") => Ok(&mut agents.planner),
+        Some("dynamic") => Ok(&mut agents.dynamic),
+        Some("fast") => Ok(&mut agents.fast),
+        Some(name) => Err(MemoryCommandError(format!("no agent named '{name}'")))
+    }
+}
+
+fn log_memory(memory: &Memory, scores: Option<(f32, f32)>) {
+    let mut details = vec![
+        format!("recall {}", memory.recall),
+        format!("recency {:.3}", memory.recency)
+    ];
+    if let Some((score, relevance)) = scores {
+        details.insert(0, format!("relevance {relevance:.3}"));
+        details.insert(0, format!("score {score:.3}"));
+    }
+
+    let metadata = &memory.metadata;
+    details.extend(metadata.namespace.iter().map(|el| format!("namespace {el}")));
+    details.extend(metadata.run.iter().map(|el| format!("run {el}")));
+    if !metadata.tools.is_empty() {
+        details.push(format!("tools {}", metadata.tools.join(", ")));
+    }
+    if metadata.timestamp > 0 {
+        details.push(format!("at {}", metadata.timestamp));
+    }
+
+    println!("{} | {}", memory.id.blue().bold(), details.join(" | ").white());
+    println!("{}", memory.content);
+    for url in &metadata.urls {
+        println!("{} {url}", "-".white());
+    }
+    println!();
+}
+
+/// Lists, searches, exports, imports and deletes an agent's memories, for any memory provider.
+pub fn run_memory_command(context: &mut CommandContext, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let command = match args.first().map(|el| el.as_str()) {
+        None | Some("help") | Some("--help") => {
+            println!("{MEMORY_USAGE}");
+            return Ok(());
+        },
+        Some(command) => command
+    };
+    let argument = args.get(1).filter(|el| !el.starts_with("--")).cloned();
+
+    let reflections = args.iter().any(|el| el == "--reflections");
+    let agent = get_agent(context, args)?;
+    let filter = get_filter(args, agent)?;
+    let AgentInfo { llm, observations, reflections: reflection_memory, .. } = agent;
+    let memory: &mut Box<dyn MemorySystem> = if reflections { reflection_memory } else { observations };
+
+    match command {
+        "list" => {
+            let memories = memory.get_all_memories_sync(&filter)?;
+            for memory in &memories {
+                log_memory(memory, None);
+            }
+            println!("{} memories.", memories.len());
+        },
+        "search" => {
+            let query = argument.ok_or(MemoryCommandError("give a query to search for".to_string()))?;
+            let count = parse_flag(args, "--count")?.unwrap_or(10);
+
+            // Searching doesn't count the memories as recalled, so it doesn't change what the agent remembers.
+            let pool = memory.get_memory_pool_sync(llm, &query, count.max(100), &filter)?;
+            let mut scored = score_memories(&pool, &query, &Weights::default()).into_iter()
+                .zip(pool.iter().map(|el| el.relevance))
+                .collect::<Vec<_>>();
+            scored.sort_by(|a, b| b.0.score.total_cmp(&a.0.score));
+
+            for (memory, relevance) in scored.into_iter().take(count) {
+                log_memory(&memory.memory, Some((memory.score, relevance)));
+            }
+        },
+        "export" => {
+            let path = argument.ok_or(MemoryCommandError("give a file to export to, or `-` for stdout".to_string()))?;
+            let memories = memory.get_all_memories_sync(&filter)?;
+
+            let mut writer: Box<dyn Write> = match path.as_str() {
+                "-" => Box::new(io::stdout()),
+                path => Box::new(BufWriter::new(File::create(path)?))
+            };
+            for memory in &memories {
+                writeln!(writer, "{}", serde_json::to_string(memory)?)?;
+            }
+            writer.flush()?;
+
+            if path != "-" {
+                println!("Exported {} memories to {path}.", memories.len());
+            }
+        },
+        "import" => {
+            let path = argument.ok_or(MemoryCommandError("give a file to import from".to_string()))?;
+
+            let mut count = 0;
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let memory_to_import: Memory = serde_json::from_str(&line)?;
+                memory.import_memory_sync(&memory_to_import)?;
+                count += 1;
+            }
+
+            println!("Imported {count} memories from {path}.");
+        },
+        "delete" => {
+            let ids = get_flags(args, "--id");
+            let filtered = FILTER_FLAGS.iter().any(|flag| args.iter().any(|el| el == flag));
+
+            let ids = match (ids.is_empty(), filtered) {
+                (false, _) => ids,
+                (true, true) => memory.get_all_memories_sync(&filter)?
+                    .into_iter()
+                    .map(|el| el.id)
+                    .collect(),
+                (true, false) => return Err(Box::new(MemoryCommandError("give an --id or a filter to delete memories".to_string())))
+            };
+
+            memory.delete_memories_sync(&ids)?;
+            println!("Deleted {} memories.", ids.len());
+        },
+        "prune" => {
+            let older_than: Option<f64> = parse_flag(args, "--older-than")?;
+            let min_recall: Option<f32> = parse_flag(args, "--min-recall")?;
+            if older_than.is_none() && min_recall.is_none() {
+                return Err(Box::new(MemoryCommandError("give --older-than or --min-recall to prune memories".to_string())));
+            }
+
+            let cutoff = older_than.map(|days| get_timestamp().saturating_sub((days * 86_400_000.) as u64));
+
+            // Memories from before they had timestamps have no known age, so they aren't pruned by it.
+            let ids = memory.get_all_memories_sync(&filter)?
+                .into_iter()
+                .filter(|el| {
+                    let old = cutoff.map(|cutoff| el.metadata.timestamp > 0 && el.metadata.timestamp < cutoff).unwrap_or(false);
+                    let unused = min_recall.map(|min_recall| el.recall < min_recall).unwrap_or(false);
+                    old || unused
+                })
+                .map(|el| el.id)
+                .collect::<Vec<_>>();
+
+            memory.delete_memories_sync(&ids)?;
+            println!("Pruned {} memories.", ids.len());
+        },
+        command => return Err(Box::new(MemoryCommandError(format!("no memory command named '{command}', see `smartgpt memory help`"))))
+    }
+
+    Ok(())
+}
@@ -1,5 +1,7 @@
 mod config;
 mod smartgpt;
+mod memories;
 
 pub use config::*;
-pub use smartgpt::*;
\ No newline at end of file
+pub use smartgpt::*;
+pub use memories::*;
\ No newline at end of file
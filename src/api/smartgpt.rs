@@ -3,7 +3,7 @@ use std::{sync::{Mutex, Arc}, collections::HashMap, error::Error, vec, fmt::Disp
 use serde::Serialize;
 use serde_json::Value;
 
-use crate::{CommandContext, PluginStore, Agents, AgentInfo, LLMProvider, LLMModel, LLM, ChatGPTProvider, ChatGPTConfig, memory_from_provider, LocalProvider, auto::{run_auto, Action, DisallowedAction, Update}, GoogleData};
+use crate::{CommandContext, PluginStore, Agents, AgentInfo, LLMProvider, LLMModel, LLM, ChatGPTProvider, ChatGPTConfig, memory_from_provider, LocalProvider, auto::{run_auto, run_assistant, load_checkpoint, Action, DisallowedAction, Update}, GoogleData, AutoType, Message};
 
 #[derive(Debug, Clone)]
 pub struct NoPluginError(pub String);
@@ -18,6 +18,8 @@ impl Error for NoPluginError {}
 
 pub struct SmartGPT {
     pub personality: String,
+    pub auto_type: AutoType,
+    pub conversation: Vec<Message>,
     pub context: Arc<Mutex<CommandContext>>
 }
 
@@ -44,6 +46,8 @@ impl SmartGPT {
         Ok(())
     }
 
+    /// Runs a task to completion. `allow_action` is only asked to confirm the actions
+    /// that the `approval` policy in the config leaves up to the user.
     pub fn run_task(
         &mut self,
         task: &str,
@@ -52,4 +56,23 @@ impl SmartGPT {
     ) -> Result<String, Box<dyn Error>> {
         run_auto(self, task, allow_action, listen_to_update)
     }
+
+    /// Restores a run from its last checkpoint, returning its task. Running that task
+    /// afterwards continues from where the run stopped.
+    pub fn resume(&mut self, run_id: &str) -> Result<String, Box<dyn Error>> {
+        let mut context = self.context.lock().unwrap();
+        load_checkpoint(&mut context, run_id)?;
+
+        Ok(context.run.task.clone())
+    }
+
+    /// Sends one message to an Assistant, keeping the conversation across calls.
+    pub fn chat(
+        &mut self,
+        message: &str,
+        allow_action: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>,
+        listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+    ) -> Result<String, Box<dyn Error>> {
+        run_assistant(self, message, allow_action, listen_to_update)
+    }
 }
\ No newline at end of file
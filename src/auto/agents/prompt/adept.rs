@@ -34,7 +34,8 @@ Respond in this JSON format:
 #[derive(Serialize, Deserialize)]
 pub struct ThoughtInfo {
     pub plan: String,
-    pub assets: String
+    pub assets: String,
+    pub reflections: String
 }
 
 pub const THOUGHTS: Prompt<ThoughtInfo> = Prompt(r#"
@@ -55,6 +56,9 @@ As you have no assets, you must pass "assets" as [] when spawning an agent.
 Ensure you adhere to your plan:
 [plan]
 
+Here are insights from your past reflections:
+[reflections]
+
 You should try to spawn agents to complete your task.
 
 Only include one `thoughts`, `reasoning`, `decision`.
@@ -25,11 +25,34 @@ Respond in this JSON format:
 }}
 ```"#, PhantomData);
 
+#[derive(Serialize, Deserialize)]
+pub struct ReflectInfo {
+    pub observations: String
+}
+
+pub const REFLECT: Prompt<ReflectInfo> = Prompt(r#"
+Here are your most recent observations:
+[observations]
+
+What are the most important high-level insights you can draw from these observations?
+Focus on lessons that will help with future tasks, not on the details of this one.
+Give at most 3 insights.
+
+Respond in this JSON format:
+```json
+{{
+	"insights": [
+		"what you learned"
+	]
+}}
+```"#, PhantomData);
+
 #[derive(Serialize, Deserialize)]
 pub struct CreatePlanInfo {
     pub tools: String,
     pub task: String,
     pub observations: String,
+    pub reflections: String,
     pub assets: String,
     pub desire: String
 }
@@ -46,6 +69,9 @@ Here is your new task:
 Here is a list of your memories:
 [observations]
 
+Here are insights from your past reflections:
+[reflections]
+
 Here is a list of assets previously saved:
 [assets]
 
@@ -140,4 +166,26 @@ Now, you will write this asset:
 
 Respond in pure plaintext format with a detailed markdown response.
 Include all necessary details as the description stated, alongside any necessary sources or explanation of where you got the information.
-"#, PhantomData);
\ No newline at end of file
+"#, PhantomData);
+#[derive(Serialize, Deserialize)]
+pub struct WriteScriptInfo {
+    pub steps: String
+}
+
+pub const WRITE_SCRIPT: Prompt<WriteScriptInfo> = Prompt(r#"
+Now you will carry out every step of your plan at once, by writing a single script:
+[steps]
+
+Scripts are written in a small subset of Python.
+Call a resource or action as a function, passing its arguments in the order they are listed: `results = google_search("query")`
+You may assign results to variables, index them like `results[0]`, and loop over lists with `for item in results:`.
+Nothing else is available; there are no `if` statements, operators, imports, or `print`. The result of every call is shown to you automatically.
+
+Include ALL information.
+Ensure you don't hallucinate; only give information that you actually have.
+
+Respond with only the script, in this format:
+```python
+script
+```
+"#, PhantomData);
@@ -1,10 +1,10 @@
-use std::{error::Error, ops::Deref, fmt::Display};
+use std::{error::Error, ops::Deref, fmt::Display, collections::HashMap};
 use colored::Colorize;
 use serde::{Serialize, Deserialize};
 
-use crate::{CommandContext, AgentInfo, Message, auto::{try_parse_json, agents::{worker::{log_yaml, run_method_agent}, prompt::{CONCISE_PLAN, ConcisePlanInfo, PersonalityInfo, PERSONALITY, THOUGHTS, ThoughtInfo, NewThoughtInfo, NEW_THOUGHTS}}, run::Action, DisallowedAction, DynamicUpdate}, ScriptValue};
+use crate::{CommandContext, AgentInfo, Message, auto::{try_parse_json, try_parse_json_streaming, agents::{worker::{log_yaml, run_method_agent}, prompt::{CONCISE_PLAN, ConcisePlanInfo, PersonalityInfo, PERSONALITY, THOUGHTS, ThoughtInfo, NewThoughtInfo, NEW_THOUGHTS}}, run::Action, DisallowedAction, DynamicUpdate, save_checkpoint}, ScriptValue, RECENCY_DECAY};
 
-use super::Update;
+use super::{Update, StreamUpdate, recall_reflections};
 
 #[derive(Debug, Clone)]
 pub struct NoDecisionTypeError(pub String);
@@ -100,6 +100,66 @@ pub fn get_response(
     }
 }
 
+fn get_thoughts(
+    agent: &mut AgentInfo,
+    prompt: String,
+    temperature: f32,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<BrainThoughts, Box<dyn Error>> {
+    agent.llm.message_history.push(Message::User(prompt));
+
+    let mut streamed = false;
+    let thoughts = try_parse_json_streaming::<BrainThoughts>(&agent.llm, 2, Some(1000), Some(temperature), &mut |token| {
+        streamed = true;
+        listen_to_update(&Update::Stream(StreamUpdate::Token(token.to_string())))
+    })?;
+    if streamed {
+        listen_to_update(&Update::Stream(StreamUpdate::End))?;
+    }
+    agent.llm.message_history.push(Message::Assistant(thoughts.raw));
+    let thoughts = thoughts.data;
+
+    listen_to_update(&Update::DynamicAgent(DynamicUpdate::Thoughts(thoughts.clone())))?;
+
+    Ok(thoughts)
+}
+
+/// Makes the next decision, unless a budget ran out, in which case the run ends with the last response.
+fn get_next_thoughts(
+    context: &mut CommandContext,
+    get_agent: &impl Fn(&mut CommandContext) -> &mut AgentInfo,
+    response: String,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<BrainThoughts, Box<dyn Error>> {
+    if let Some(reason) = context.budget.check(context.run.progress.decisions) {
+        listen_to_update(&Update::DynamicAgent(DynamicUpdate::BudgetExhausted(reason.clone())))?;
+
+        let response = format!("The task was stopped early because {reason}. The last result was:\n\n{response}");
+        return Ok(BrainThoughts {
+            thoughts: format!("I have to stop, because {reason}."),
+            reasoning: "No budget is left to make more decisions.".to_string(),
+            decision: Decision {
+                decision_type: "final_response".to_string(),
+                args: ScriptValue::Dict(HashMap::from([
+                    ("response".to_string(), response.into())
+                ]))
+            }
+        });
+    }
+
+    context.run.progress.decisions += 1;
+
+    get_thoughts(
+        get_agent(context),
+        NEW_THOUGHTS.fill(NewThoughtInfo {
+            response,
+            assets: "None found.".to_string() 
+        })?,
+        0.5,
+        listen_to_update
+    )
+}
+
 pub fn run_brain_agent(
     context: &mut CommandContext, 
     get_agent: &impl Fn(&mut CommandContext) -> &mut AgentInfo,
@@ -108,76 +168,50 @@ pub fn run_brain_agent(
     allow_action: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>,
     listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
 ) -> Result<String, Box<dyn Error>> {
-    let agent = get_agent(context);
-    
-    agent.llm.prompt.push(Message::System(
-        PERSONALITY.fill(PersonalityInfo { personality: personality.to_string() })?
-    ));
-    agent.llm.prompt.push(Message::User(
-        CONCISE_PLAN.fill(ConcisePlanInfo { task: task.to_string() })?
-    ));
-
-    let plan = try_parse_json::<DynamicPlan>(&agent.llm, 2, Some(1000), Some(0.3))?;
-    agent.llm.message_history.push(Message::Assistant(plan.raw));
-    let plan = plan.data;  
-
-    listen_to_update(&Update::DynamicAgent(DynamicUpdate::Plan(plan.plan.clone())))?;
-
-    agent.llm.message_history.push(Message::User(
-        THOUGHTS.fill(ThoughtInfo {
-            plan: plan.plan,
-            assets: "None found.".to_string() 
-        })?
-    ));
+    let progress = context.run.progress.clone();
 
-    let thoughts = try_parse_json::<BrainThoughts>(&agent.llm, 2, Some(1000), Some(0.3))?;
-    agent.llm.message_history.push(Message::Assistant(thoughts.raw));
-    let thoughts = thoughts.data;  
+    let mut thoughts = if let Some(thoughts) = progress.decision {
+        // The run stopped while carrying out this decision, so carry on with it.
+        thoughts
+    } else if let Some(response) = progress.response {
+        get_next_thoughts(context, get_agent, response, listen_to_update)?
+    } else {
+        context.run.progress.decisions += 1;
 
-    listen_to_update(&Update::DynamicAgent(DynamicUpdate::Thoughts(thoughts.clone())))?;
-
-    drop(agent);
-    let mut response = get_response(
-        context, 
-        &|ctx| &mut ctx.agents.static_agent, 
-        &|ctx| &mut ctx.agents.planner, 
-        &thoughts, 
-        &personality,
-        allow_action,
-        listen_to_update
-    )?;
+        let reflections = recall_reflections(&mut context.agents.static_agent, task, 10)?;
 
-    if thoughts.decision.decision_type == "final_response" {
-        return Ok(response);
-    }
-    
-    loop {
-        let cloned_assets = context.assets.clone();
-        let asset_list = if cloned_assets.len() == 0 {
-            format!("No assets.")
-        } else {
-            cloned_assets
-                .keys()
-                .map(|asset| asset.clone())
-                .collect::<Vec<_>>()
-                .join(", ")
-        };
         let agent = get_agent(context);
-
-        agent.llm.message_history.push(Message::User(
-            NEW_THOUGHTS.fill(NewThoughtInfo {
-                response: response.to_string(),
-                assets: "None found.".to_string() 
-            })?
+        
+        agent.llm.prompt.push(Message::System(
+            PERSONALITY.fill(PersonalityInfo { personality: personality.to_string() })?
         ));
+        agent.llm.prompt.push(Message::User(
+            CONCISE_PLAN.fill(ConcisePlanInfo { task: task.to_string() })?
+        ));
+
+        let plan = try_parse_json::<DynamicPlan>(&agent.llm, 2, Some(1000), Some(0.3))?;
+        agent.llm.message_history.push(Message::Assistant(plan.raw));
+        let plan = plan.data;  
 
-        let thoughts = try_parse_json::<BrainThoughts>(&agent.llm, 2, Some(1000), Some(0.5))?;
-        agent.llm.message_history.push(Message::Assistant(thoughts.raw));
-        let thoughts = thoughts.data; 
+        listen_to_update(&Update::DynamicAgent(DynamicUpdate::Plan(plan.plan.clone())))?;
 
-        listen_to_update(&Update::DynamicAgent(DynamicUpdate::Thoughts(thoughts.clone())))?;
+        get_thoughts(
+            agent,
+            THOUGHTS.fill(ThoughtInfo {
+                plan: plan.plan,
+                assets: "None found.".to_string(),
+                reflections
+            })?,
+            0.3,
+            listen_to_update
+        )?
+    };
+    
+    loop {
+        context.run.progress.decision = Some(thoughts.clone());
+        save_checkpoint(context)?;
 
-        response = get_response(
+        let response = get_response(
             context, 
             &|ctx| &mut ctx.agents.static_agent, 
             &|ctx| &mut ctx.agents.planner, 
@@ -190,5 +224,14 @@ pub fn run_brain_agent(
         if thoughts.decision.decision_type == "final_response" {
             return Ok(response);
         }
+
+        context.agents.decay_memories(RECENCY_DECAY)?;
+
+        context.run.progress.decision = None;
+        context.run.progress.response = Some(response.clone());
+        context.run.progress.subtask = None;
+        save_checkpoint(context)?;
+
+        thoughts = get_next_thoughts(context, get_agent, response, listen_to_update)?;
     }
-}
\ No newline at end of file
+}
@@ -1,11 +1,12 @@
-use std::{error::Error, collections::{HashSet}};
+use std::error::Error;
 
 use colored::Colorize;
+use regex::Regex;
 use serde::{Serialize, Deserialize};
 
-use crate::{CommandContext, AgentInfo, Message, auto::{run::Action, try_parse_json, agents::{worker::create_tool_list, prompt::{SUMMARIZE_MEMORIES, NoData, PERSONALITY, PersonalityInfo, CREATE_PLAN, CreatePlanInfo, NextStepInfo, NEXT_STEP, SAVE_ASSET, SaveAssetInfo}}, DisallowedAction, StaticUpdate, Update, NamedAsset}, Weights, Tool};
+use crate::{CommandContext, AgentInfo, Message, auto::{run::Action, try_parse_json, agents::{worker::create_tool_list, prompt::{SUMMARIZE_MEMORIES, NoData, PERSONALITY, PersonalityInfo, CREATE_PLAN, CreatePlanInfo, NextStepInfo, NEXT_STEP, SAVE_ASSET, SaveAssetInfo}}, DisallowedAction, StaticUpdate, StreamUpdate, Update, NamedAsset, get_tool_type, SubtaskProgress, save_checkpoint}, Weights, Tool, MemoryMetadata, MemoryFilter, get_timestamp};
 
-use super::{log_yaml, use_tool};
+use super::{log_yaml, use_tool, run_script, add_observations, recall_reflections, StaticMode};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct MethodicalThoughts {
@@ -77,34 +78,123 @@ pub fn add_memories(
     let memories = try_parse_json::<Memories>(&agent.llm, 2, Some(700), Some(0.5))?.data;
     listen_to_update(&Update::StaticAgent(StaticUpdate::SavedMemories(memories.clone())))?;
 
+    agent.memory_metadata.timestamp = get_timestamp();
     for memory in memories.actions.iter().chain(memories.observations.iter()) {
-        agent.observations.store_memory_sync(&agent.llm, memory)?;
+        agent.observations.store_memory_sync(&agent.llm, memory, &agent.memory_metadata)?;
+    }
+    agent.memory_metadata.tools.clear();
+    agent.memory_metadata.urls.clear();
+
+    add_observations(agent, &memories.observations, listen_to_update)
+}
+
+pub fn add_action_results(
+    agent: &mut AgentInfo,
+    out: String,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<(), Box<dyn Error>> {
+    listen_to_update(&Update::StaticAgent(StaticUpdate::ActionResults(out.clone())))?;
+    agent.llm.message_history.push(Message::User(out));
+
+    let tokens = agent.llm.get_tokens_remaining(&agent.llm.get_messages())?;
+    if tokens < 1200 {
+        match add_memories(agent, listen_to_update) {
+            Ok(_) => {},
+            Err(_) => {
+                agent.llm.crop_to_tokens_remaining(1000)?;
+            }
+        };
+        agent.llm.crop_to_tokens_remaining(2000)?;
     }
 
     Ok(())
 }
 
-pub fn run_method_agent(
+/// Notes the tool an action uses, and the URLs it was given, as where the agent's next memories come from.
+fn add_sources(metadata: &mut MemoryMetadata, action: &Action) -> Result<(), Box<dyn Error>> {
+    if !metadata.tools.contains(&action.tool) {
+        metadata.tools.push(action.tool.clone());
+    }
+
+    let args = serde_json::to_string(&action.args)?;
+    let url_pattern = Regex::new(r#"https?://[^\s"'<>]+"#)?;
+    for url in url_pattern.find_iter(&args) {
+        let url = url.as_str().to_string();
+        if !metadata.urls.contains(&url) {
+            metadata.urls.push(url);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn run_steps(
     context: &mut CommandContext, 
     get_agent: &impl Fn(&mut CommandContext) -> &mut AgentInfo,
+    plan: &MethodicalPlan,
+    completed_steps: usize,
+    allow_action: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<(), Box<dyn Error>> {
+    for (ind, step) in plan.steps.clone().into_iter().enumerate().skip(completed_steps) {
+        if context.budget.check_usage().is_some() {
+            break;
+        }
+
+        let agent = get_agent(context);
+        let step_text = serde_yaml::to_string(&step)?;
+        
+        listen_to_update(&Update::StaticAgent(StaticUpdate::SelectedStep(step.clone())))?;
+
+        agent.llm.message_history.push(Message::User(
+            NEXT_STEP.fill(NextStepInfo {
+                step: step_text
+            })?
+        ));
+
+        let thoughts = try_parse_json::<MethodicalThoughts>(&agent.llm, 2, Some(1000), Some(0.5))?;
+        agent.llm.message_history.push(Message::Assistant(thoughts.raw));
+        let thoughts = thoughts.data;
+
+        drop(agent);
+
+        listen_to_update(&Update::StaticAgent(StaticUpdate::Thoughts(thoughts.clone())))?;
+        add_sources(&mut get_agent(context).memory_metadata, &thoughts.action)?;
+
+        let tool_type = get_tool_type(context, &thoughts.action.tool);
+        let out = match context.approval.clone().approve(&tool_type, &thoughts.action, allow_action) {
+            Ok(()) => use_tool(context, &|context| &mut context.agents.fast, thoughts.action.clone())?,
+            Err(DisallowedAction(err)) => format!("Error: {err}")
+        };
+
+        add_action_results(get_agent(context), out, listen_to_update)?;
+
+        if let Some(subtask) = &mut context.run.progress.subtask {
+            subtask.completed_steps = ind + 1;
+        }
+        save_checkpoint(context)?;
+    }
+
+    Ok(())
+}
+
+pub fn create_plan(
+    context: &mut CommandContext, 
     get_planner_agent: &impl Fn(&mut CommandContext) -> &mut AgentInfo,
     task: &str,
     desire: &str,
     assets: Option<String>,
     personality: &str,
-    allow_action: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>,
     listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
-) -> Result<String, Box<dyn Error>> {
+) -> Result<MethodicalPlan, Box<dyn Error>> {
     let tools: Vec<&Tool> = context.plugins.iter()
         .flat_map(|plugin| &plugin.tools)
         .collect();
     
     let tools = create_tool_list(&tools);
-    
-    let cloned_assets = context.assets.clone();
-    let assets_before: HashSet<&String> = cloned_assets.keys().collect();
 
-    get_agent(context).llm.clear_history();
+    // Reflections are made by the static agent, which carries out the plan.
+    let reflections = recall_reflections(&mut context.agents.static_agent, task, 10)?;
 
     let planner = get_planner_agent(context);
 
@@ -114,12 +204,17 @@ pub fn run_method_agent(
         PERSONALITY.fill(PersonalityInfo { personality: personality.to_string() })?
     ));
 
+    let filter = MemoryFilter {
+        namespace: planner.memory_metadata.namespace.clone(),
+        ..Default::default()
+    };
     let observations = planner.observations.get_memories_sync(
         &planner.llm, task, 100, Weights {
             recall: 1.,
             recency: 1.,
-            relevance: 1.
-        }, 30
+            relevance: 1.,
+            keyword: 1.
+        }, 30, &filter
     )?;
 
     let observations = if observations.len() == 0 {
@@ -137,6 +232,7 @@ pub fn run_method_agent(
         CREATE_PLAN.fill(CreatePlanInfo {
             task: task.to_string(),
             observations,
+            reflections,
             tools,
             assets: data,
             desire: desire.to_string()
@@ -145,68 +241,85 @@ pub fn run_method_agent(
 
     let plan = try_parse_json::<MethodicalPlan>(&planner.llm, 2, Some(600), Some(0.3))?;
     planner.llm.message_history.push(Message::Assistant(plan.raw));
-    let plan = plan.data;
-    listen_to_update(&Update::StaticAgent(StaticUpdate::Plan(plan.clone())))?;
-
-    let prompt = planner.llm.prompt.clone();
-    let message_history = planner.llm.message_history.clone();
+    let mut plan = plan.data;
 
-    drop(planner);
-
-    let agent = get_agent(context);
-    agent.llm.prompt = prompt;
-    agent.llm.message_history = message_history;
+    if let Some(max_steps) = context.budget.limits.max_steps {
+        plan.steps.truncate(max_steps);
+    }
 
-    for step in plan.steps {
-        let agent = get_agent(context);
-        let step_text = serde_yaml::to_string(&step)?;
-        
-        listen_to_update(&Update::StaticAgent(StaticUpdate::SelectedStep(step.clone())))?;
+    listen_to_update(&Update::StaticAgent(StaticUpdate::Plan(plan.clone())))?;
 
-        agent.llm.message_history.push(Message::User(
-            NEXT_STEP.fill(NextStepInfo {
-                step: step_text
-            })?
-        ));
+    Ok(plan)
+}
 
-        let thoughts = try_parse_json::<MethodicalThoughts>(&agent.llm, 2, Some(1000), Some(0.5))?;
-        agent.llm.message_history.push(Message::Assistant(thoughts.raw));
-        let thoughts = thoughts.data;
+pub fn run_method_agent(
+    context: &mut CommandContext, 
+    get_agent: &impl Fn(&mut CommandContext) -> &mut AgentInfo,
+    get_planner_agent: &impl Fn(&mut CommandContext) -> &mut AgentInfo,
+    task: &str,
+    desire: &str,
+    assets: Option<String>,
+    personality: &str,
+    allow_action: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<String, Box<dyn Error>> {
+    let (run, run_task) = (context.run.id.clone(), context.run.task.clone());
+    let metadata = &mut get_agent(context).memory_metadata;
+    metadata.run = Some(run);
+    metadata.task = Some(run_task);
+
+    // A subtask that was already planned picks up from its last completed step.
+    let SubtaskProgress { plan, completed_steps, saved_assets } = match context.run.progress.subtask.clone() {
+        Some(subtask) => subtask,
+        None => {
+            let plan = create_plan(context, get_planner_agent, task, desire, assets, personality, listen_to_update)?;
+
+            let planner = get_planner_agent(context);
+            let prompt = planner.llm.prompt.clone();
+            let message_history = planner.llm.message_history.clone();
+
+            let agent = get_agent(context);
+            agent.llm.prompt = prompt;
+            agent.llm.message_history = message_history;
+
+            let subtask = SubtaskProgress {
+                plan,
+                completed_steps: 0,
+                saved_assets: 0
+            };
+            context.run.progress.subtask = Some(subtask.clone());
+            save_checkpoint(context)?;
+
+            subtask
+        }
+    };
 
-        drop(agent);
+    if context.static_mode == StaticMode::Script {
+        if completed_steps < plan.steps.len() {
+            run_script(context, get_agent, &plan, allow_action, listen_to_update)?;
 
-        listen_to_update(&Update::StaticAgent(StaticUpdate::Thoughts(thoughts.clone())))?;
-        allow_action(&thoughts.action)?;
-
-        let out = use_tool(context, &|context| &mut context.agents.fast, thoughts.action.clone());
-            
-        match out {
-            Ok(out) => {
-                let agent = get_agent(context);
-
-                listen_to_update(&Update::StaticAgent(StaticUpdate::ActionResults(out.clone())))?;
-                agent.llm.message_history.push(Message::User(out));
-
-                let tokens = agent.llm.get_tokens_remaining(&agent.llm.get_messages())?;
-                if tokens < 1200 {
-                    match add_memories(agent, listen_to_update) {
-                        Ok(_) => {},
-                        Err(_) => {
-                            agent.llm.crop_to_tokens_remaining(1000)?;
-                        }
-                    };
-                    agent.llm.crop_to_tokens_remaining(2000)?;
-                }
-            },
-            Err(err) => {
-                return Err(err);
+            if let Some(subtask) = &mut context.run.progress.subtask {
+                subtask.completed_steps = plan.steps.len();
             }
+            save_checkpoint(context)?;
         }
+    } else {
+        run_steps(context, get_agent, &plan, completed_steps, allow_action, listen_to_update)?;
     }
 
-    let mut changed_assets: Vec<NamedAsset> = vec![];
+    // Saving assets and memories costs more tokens, so a subtask that ran out of budget stops here.
+    if let Some(reason) = context.budget.check_usage() {
+        return Ok(format!("The subtask was stopped early because {reason}."));
+    }
+
+    let mut changed_assets: Vec<NamedAsset> = plan.assets.iter()
+        .take(saved_assets)
+        .filter_map(|asset| context.assets.get(&asset.name)
+            .map(|content| NamedAsset(asset.name.clone(), content.clone()))
+        )
+        .collect();
 
-    for asset in plan.assets {
+    for (ind, asset) in plan.assets.into_iter().enumerate().skip(saved_assets) {
         listen_to_update(&Update::StaticAgent(StaticUpdate::SelectedAsset(asset.name.clone())))?;
 
         let agent = get_agent(context);
@@ -217,8 +330,12 @@ pub fn run_method_agent(
             SAVE_ASSET.fill(SaveAssetInfo { asset: asset_text })?
         ));
     
-        let asset_content = agent.llm.model.get_response_sync(&agent.llm.get_messages(), Some(800), Some(0.3))?;
+        let asset_content = agent.llm.model.get_response_streaming_sync(
+            &agent.llm.get_messages(), Some(800), Some(0.3),
+            &mut |token| listen_to_update(&Update::Stream(StreamUpdate::Token(token.to_string())))
+        )?;
         agent.llm.message_history.pop();
+        listen_to_update(&Update::Stream(StreamUpdate::End))?;
 
         drop(agent);
 
@@ -229,6 +346,11 @@ pub fn run_method_agent(
         let named_asset = NamedAsset(asset.name, asset_content);
         changed_assets.push(named_asset.clone());
         listen_to_update(&Update::StaticAgent(StaticUpdate::AddedAsset(named_asset.clone())))?;
+
+        if let Some(subtask) = &mut context.run.progress.subtask {
+            subtask.saved_assets = ind + 1;
+        }
+        save_checkpoint(context)?;
     }
 
     let agent = get_agent(context);
@@ -1,16 +1,20 @@
-use std::{error::Error};
-use crate::{SmartGPT, AgentInfo, auto::{run::Action, DisallowedAction}};
+use std::{error::Error, sync::Mutex};
+use crate::{SmartGPT, AgentInfo, RunUsage, create_run_id, auto::{run::Action, DisallowedAction, Progress, clear_checkpoint}};
 use serde::Serialize;
 
 mod adept;
 mod actor;
 mod methodical;
+mod reflect;
+mod script;
 mod tools;
 mod updates;
 
 pub use adept::*;
 pub use actor::*;
 pub use methodical::*;
+pub use reflect::*;
+pub use script::*;
 pub use tools::*;
 pub use updates::*;
 
@@ -23,18 +27,60 @@ pub fn run_worker(
 ) -> Result<String, Box<dyn Error>> {
     let mut context = smartgpt.context.lock().unwrap();
 
-    let response = run_brain_agent(
+    // Progress left over from another task can't be resumed, so that task starts over. A resumed
+    // run keeps the budget it had used, which was restored with its checkpoint.
+    if context.run.task != task {
+        // Each task an Assistant runs gets its own run, so their checkpoints, events and memories stay apart.
+        if context.run.started {
+            context.run.id = create_run_id();
+            listen_to_update(&Update::NewRun(context.run.id.clone()))?;
+        }
+
+        context.run.task = task.to_string();
+        context.run.progress = Progress::default();
+        context.budget.reset();
+    }
+    context.run.started = true;
+
+    let usage = context.budget.usage.clone();
+
+    let result = run_brain_agent(
         &mut context, 
         &|ctx| &mut ctx.agents.dynamic, 
         task, 
         personality,
         allow_action,
-        listen_to_update
-    )?;
+        &mut |update| {
+            // Models record their usage as they go, so it's sent out ahead of whatever update comes next.
+            send_usage(&usage, listen_to_update)?;
+            listen_to_update(update)
+        }
+    );
+
+    // The summary is sent even if the run failed, since the tokens were still spent.
+    send_usage(&usage, listen_to_update)?;
+    let summary = usage.lock().unwrap().summary.clone();
+    listen_to_update(&Update::Usage(UsageUpdate::Summary(summary)))?;
+
+    let response = result?;
+
+    clear_checkpoint(&mut context)?;
 
     Ok(response)
 }
 
+pub fn send_usage(
+    usage: &Mutex<RunUsage>,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<(), Box<dyn Error>> {
+    let calls = usage.lock().unwrap().take_pending();
+    for call in calls {
+        listen_to_update(&Update::Usage(UsageUpdate::Call(call)))?;
+    }
+
+    Ok(())
+}
+
 pub fn log_yaml<T: Serialize>(data: &T) -> Result<(), Box<dyn Error>> {
     println!("{}", serde_yaml::to_string(&data)?);
 
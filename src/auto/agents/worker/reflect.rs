@@ -0,0 +1,100 @@
+use std::error::Error;
+
+use colored::Colorize;
+use serde::{Serialize, Deserialize};
+
+use crate::{AgentInfo, Message, Weights, MemoryMetadata, MemoryFilter, get_timestamp, auto::{try_parse_json, agents::prompt::{REFLECT, ReflectInfo}, StaticUpdate, Update}};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Reflections {
+    pub insights: Vec<String>
+}
+
+/// Keeps the observations the agent hasn't reflected on, and reflects on them once there are enough.
+pub fn add_observations(
+    agent: &mut AgentInfo,
+    observations: &[String],
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<(), Box<dyn Error>> {
+    agent.unreflected.extend(observations.iter().cloned());
+
+    let Some(reflect_every) = agent.reflect_every else {
+        agent.unreflected.clear();
+        return Ok(());
+    };
+
+    if agent.unreflected.len() < reflect_every.max(1) {
+        return Ok(());
+    }
+
+    // The observations are kept on failure, to be reflected on with the next ones.
+    match reflect(agent, listen_to_update) {
+        Ok(()) => agent.unreflected.clear(),
+        Err(err) => eprintln!("{}", format!("Could not reflect: {err}").red())
+    }
+
+    Ok(())
+}
+
+/// Synthesizes insights from the observations the agent hasn't reflected on, and stores them as reflections.
+pub fn reflect(
+    agent: &mut AgentInfo,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<(), Box<dyn Error>> {
+    let observations = agent.unreflected.iter()
+        .map(|el| format!("- {el}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // Reflecting happens in the middle of the agent's work, so its conversation is put back afterwards.
+    let prompt = std::mem::take(&mut agent.llm.prompt);
+    let message_history = std::mem::take(&mut agent.llm.message_history);
+    let end_prompt = std::mem::take(&mut agent.llm.end_prompt);
+
+    let reflections = REFLECT.fill(ReflectInfo { observations })
+        .and_then(|prompt| {
+            agent.llm.prompt.push(Message::User(prompt));
+            try_parse_json::<Reflections>(&agent.llm, 2, Some(600), Some(0.5))
+        });
+
+    agent.llm.prompt = prompt;
+    agent.llm.message_history = message_history;
+    agent.llm.end_prompt = end_prompt;
+
+    let reflections = reflections?.data;
+    listen_to_update(&Update::StaticAgent(StaticUpdate::Reflected(reflections.clone())))?;
+
+    // Reflections sum up many observations, so they don't keep the tools and URLs of any one.
+    let metadata = MemoryMetadata {
+        namespace: agent.memory_metadata.namespace.clone(),
+        run: agent.memory_metadata.run.clone(),
+        task: agent.memory_metadata.task.clone(),
+        timestamp: get_timestamp(),
+        ..Default::default()
+    };
+    for insight in &reflections.insights {
+        agent.reflections.store_memory_sync(&agent.llm, insight, &metadata)?;
+    }
+
+    Ok(())
+}
+
+/// Lists the agent's reflections that are most relevant to `query`.
+pub fn recall_reflections(agent: &mut AgentInfo, query: &str, count: usize) -> Result<String, Box<dyn Error>> {
+    let filter = MemoryFilter {
+        namespace: agent.memory_metadata.namespace.clone(),
+        ..Default::default()
+    };
+    let reflections = agent.reflections.get_memories_sync(&agent.llm, query, 50, Weights::default(), count, &filter)?;
+
+    if reflections.is_empty() {
+        return Ok("None found.".to_string());
+    }
+
+    Ok(
+        reflections.iter()
+            .map(|el| format!("- {}", el.content))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
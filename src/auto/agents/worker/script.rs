@@ -0,0 +1,64 @@
+use std::error::Error;
+
+use serde::{Serialize, Deserialize};
+use tokio::runtime::Runtime;
+
+use crate::{CommandContext, AgentInfo, Message, Interpreter, ScriptCall, parse_gptscript, auto::{run::Action, try_parse_base, agents::prompt::{WRITE_SCRIPT, WriteScriptInfo}, DisallowedAction, StaticUpdate, Update}};
+
+use super::{MethodicalPlan, add_action_results};
+
+/// How the static agent carries out the steps of its plan.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StaticMode {
+    /// One LLM request per step, each running a single tool.
+    #[default]
+    #[serde(rename = "steps")] Steps,
+    /// One LLM request per subtask, writing a GPTScript program that runs every step.
+    #[serde(rename = "script")] Script
+}
+
+pub fn run_script(
+    context: &mut CommandContext,
+    get_agent: &impl Fn(&mut CommandContext) -> &mut AgentInfo,
+    plan: &MethodicalPlan,
+    allow_action: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<(), Box<dyn Error>> {
+    let agent = get_agent(context);
+
+    agent.llm.message_history.push(Message::User(
+        WRITE_SCRIPT.fill(WriteScriptInfo {
+            steps: serde_yaml::to_string(&plan.steps)?
+        })?
+    ));
+
+    let script = try_parse_base(&agent.llm, 2, Some(1000), Some(0.3), "python", |code| {
+        Ok((code.to_string(), parse_gptscript(code)?))
+    })?;
+    agent.llm.message_history.push(Message::Assistant(script.raw));
+    let (code, body) = script.data;
+
+    listen_to_update(&Update::StaticAgent(StaticUpdate::Script(code)))?;
+
+    let approval = context.approval.clone();
+    let mut allow_call = |call: &ScriptCall| {
+        let action = Action {
+            tool: call.tool.clone(),
+            args: Some(call.args.clone())
+        };
+
+        approval.approve(&call.tool_type, &action, allow_action)
+            .map_err(|err| Box::new(err) as Box<dyn Error>)
+    };
+
+    let mut interpreter = Interpreter::new(context, &mut allow_call);
+    let result = Runtime::new()?.block_on(interpreter.run_body(&body));
+    let mut out = interpreter.out.join("\n\n");
+
+    // The agent still sees the results of every call that ran before the error.
+    if let Err(err) = result {
+        out.push_str(&format!("\n\nError: {err}"));
+    }
+
+    add_action_results(get_agent(context), out.trim().to_string(), listen_to_update)
+}
@@ -7,7 +7,7 @@ pub fn create_filtered_tool_list(header: &str, tools: &[&Tool], tool_type: ToolT
 
     for tool in tools {
         // Compares if the tool types are the same, not the values of the tool types
-        if discriminant(&tool.tool_type) == discriminant(&tool_type) {
+        if discriminant(&tool.tool_type) != discriminant(&tool_type) {
             continue;
         }
 
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 
-use super::{BrainThoughts, MethodicalPlan, MethodicalThoughts, MethodicalStep, Memories};
+use crate::{CallUsage, UsageSummary};
+
+use super::{BrainThoughts, MethodicalPlan, MethodicalThoughts, MethodicalStep, Memories, Reflections};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NamedAsset(pub String, pub String);
@@ -10,7 +12,9 @@ pub enum DynamicUpdate {
     #[serde(rename = "plan")]
     Plan(String),
     #[serde(rename = "thoughts")]
-    Thoughts(BrainThoughts)
+    Thoughts(BrainThoughts),
+    #[serde(rename = "budget exhausted")]
+    BudgetExhausted(String)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -19,6 +23,8 @@ pub enum StaticUpdate {
     Plan(MethodicalPlan),
     #[serde(rename = "selected step")]
     SelectedStep(MethodicalStep),
+    #[serde(rename = "script")]
+    Script(String),
     #[serde(rename = "thoughts")]
     Thoughts(MethodicalThoughts),
     #[serde(rename = "action results")]
@@ -30,7 +36,26 @@ pub enum StaticUpdate {
     #[serde(rename = "saving memories")]
     SavingMemories(),
     #[serde(rename = "added memories")]
-    SavedMemories(Memories)
+    SavedMemories(Memories),
+    #[serde(rename = "added reflections")]
+    Reflected(Reflections)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum UsageUpdate {
+    #[serde(rename = "call")]
+    Call(CallUsage),
+    #[serde(rename = "summary")]
+    Summary(UsageSummary)
+}
+
+/// A response coming in piece by piece, before it's parsed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum StreamUpdate {
+    #[serde(rename = "token")]
+    Token(String),
+    #[serde(rename = "end")]
+    End
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -38,5 +63,12 @@ pub enum Update {
     #[serde(rename = "dynamic agent")]
     DynamicAgent(DynamicUpdate),
     #[serde(rename = "static agent")]
-    StaticAgent(StaticUpdate)
+    StaticAgent(StaticUpdate),
+    #[serde(rename = "usage")]
+    Usage(UsageUpdate),
+    #[serde(rename = "stream")]
+    Stream(StreamUpdate),
+    /// Another task started, with the ID of its own run.
+    #[serde(rename = "new run")]
+    NewRun(String)
 }
\ No newline at end of file
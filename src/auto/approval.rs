@@ -0,0 +1,93 @@
+use std::{error::Error, fmt::Display};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{ToolType, CommandContext};
+
+use super::{run::Action, DisallowedAction};
+
+#[derive(Debug, Clone)]
+pub struct NotApprovedError(pub String, pub String);
+
+impl Display for NotApprovedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the '{}' tool was not approved: {}", self.0, self.1)
+    }
+}
+
+impl Error for NotApprovedError {}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ApprovalMode {
+    /// Ask before running any action that needs permission.
+    #[default]
+    #[serde(rename = "ask")] Ask,
+    /// Run every action without asking.
+    #[serde(rename = "allow all")] AllowAll,
+    /// Never run actions, for non-interactive runs that must not have side effects.
+    #[serde(rename = "deny all")] DenyAll
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalPolicy {
+    #[serde(default)] pub mode: ApprovalMode,
+    #[serde(default)] pub allow: Vec<String>,
+    #[serde(default)] pub deny: Vec<String>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Approval {
+    Allowed,
+    Denied(String),
+    NeedsConfirmation
+}
+
+impl ApprovalPolicy {
+    pub fn check(&self, tool: &str, tool_type: &ToolType) -> Approval {
+        if self.deny.iter().any(|el| el == tool) {
+            return Approval::Denied("it is on the deny list".to_string());
+        }
+
+        let needs_permission = match tool_type {
+            ToolType::Resource => return Approval::Allowed,
+            ToolType::Action { needs_permission } => *needs_permission
+        };
+
+        if self.allow.iter().any(|el| el == tool) {
+            return Approval::Allowed;
+        }
+
+        match self.mode {
+            ApprovalMode::AllowAll => Approval::Allowed,
+            ApprovalMode::DenyAll => Approval::Denied("actions are not allowed in this run".to_string()),
+            ApprovalMode::Ask if needs_permission => Approval::NeedsConfirmation,
+            ApprovalMode::Ask => Approval::Allowed
+        }
+    }
+
+    /// Checks an action against the policy, only calling `confirm` when the policy
+    /// can't decide on its own.
+    pub fn approve(
+        &self,
+        tool_type: &ToolType,
+        action: &Action,
+        confirm: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>
+    ) -> Result<(), DisallowedAction> {
+        match self.check(&action.tool, tool_type) {
+            Approval::Allowed => Ok(()),
+            Approval::Denied(reason) => Err(DisallowedAction(Box::new(
+                NotApprovedError(action.tool.clone(), reason)
+            ))),
+            Approval::NeedsConfirmation => confirm(action)
+        }
+    }
+}
+
+/// Unknown tools are treated as resources; running them will fail with its own error.
+pub fn get_tool_type(context: &CommandContext, tool: &str) -> ToolType {
+    context.plugins.iter()
+        .flat_map(|el| &el.tools)
+        .find(|el| el.name == tool)
+        .map(|el| el.tool_type.clone())
+        .unwrap_or(ToolType::Resource)
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+
+use crate::{SmartGPT, Message, auto::agents::prompt::{PERSONALITY, PersonalityInfo}};
+
+use super::{classify::is_task, run::Action, DisallowedAction, Update, run_worker};
+
+pub fn format_conversation(conversation: &[Message]) -> String {
+    conversation.iter()
+        .map(|message| format!("{}: {}", 
+            match message {
+                Message::Assistant(_) => "ASSISTANT",
+                _ => "USER"
+            },
+            message.content()
+        ))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn create_assistant_task(conversation: &[Message], message: &str) -> String {
+    if conversation.is_empty() {
+        return message.to_string();
+    }
+
+    format!(
+r#"Here is your conversation with the user so far:
+{}
+
+The user has now asked:
+{message}"#, format_conversation(conversation))
+}
+
+pub fn respond_directly(smartgpt: &mut SmartGPT, message: &str) -> Result<String, Box<dyn Error>> {
+    let SmartGPT {
+        context, personality, conversation, ..
+    } = smartgpt;
+    let mut context = context.lock().unwrap();
+    let agent = &mut context.agents.dynamic;
+
+    agent.llm.clear_history();
+    agent.llm.prompt.push(Message::System(
+        PERSONALITY.fill(PersonalityInfo { personality: personality.clone() })?
+    ));
+    agent.llm.message_history.extend(conversation.iter().cloned());
+    agent.llm.message_history.push(Message::User(message.to_string()));
+
+    let response = agent.llm.model.get_response_sync(
+        &agent.llm.get_messages(), Some(1000), None
+    )?;
+
+    agent.llm.clear_history();
+
+    Ok(response)
+}
+
+pub fn run_assistant(
+    smartgpt: &mut SmartGPT,
+    message: &str,
+    allow_action: &mut impl FnMut(&Action) -> Result<(), DisallowedAction>,
+    listen_to_update: &mut impl FnMut(&Update) -> Result<(), Box<dyn Error>>
+) -> Result<String, Box<dyn Error>> {
+    // The message is classified along with the conversation, so a follow-up like "go ahead"
+    // is understood as the task it refers to.
+    let task = create_assistant_task(&smartgpt.conversation, message);
+    let response = if is_task(smartgpt, &task)? {
+        smartgpt.context.lock().unwrap().agents.dynamic.llm.clear_history();
+
+        let personality = smartgpt.personality.clone();
+        run_worker(smartgpt, &task, &personality, allow_action, listen_to_update)?
+    } else {
+        respond_directly(smartgpt, message)?
+    };
+
+    smartgpt.conversation.push(Message::User(message.to_string()));
+    smartgpt.conversation.push(Message::Assistant(response.clone()));
+
+    Ok(response)
+}
@@ -0,0 +1,92 @@
+use std::{sync::{Arc, Mutex}, time::{Instant, Duration}};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{RunUsage, UsageSummary};
+
+/// Limits on how much a single run can do. Anything left out is unlimited.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BudgetLimits {
+    #[serde(rename = "max decisions", default)] pub max_decisions: Option<usize>,
+    /// The most steps the static agent runs for one subtask.
+    #[serde(rename = "max steps", default)] pub max_steps: Option<usize>,
+    #[serde(rename = "max tokens", default)] pub max_tokens: Option<usize>,
+    /// In dollars, estimated from the model prices.
+    #[serde(rename = "max cost", default)] pub max_cost: Option<f64>,
+    /// In seconds.
+    #[serde(rename = "max time", default)] pub max_time: Option<u64>
+}
+
+pub struct Budget {
+    pub limits: BudgetLimits,
+    /// Shared with every agent's model, which adds to it after each response.
+    pub usage: Arc<Mutex<RunUsage>>,
+    pub started: Instant,
+    /// Time the run spent before it was resumed.
+    pub previous: Duration
+}
+
+impl Budget {
+    pub fn new(limits: BudgetLimits) -> Self {
+        Self {
+            limits,
+            usage: Arc::new(Mutex::new(RunUsage::default())),
+            started: Instant::now(),
+            previous: Duration::ZERO
+        }
+    }
+
+    /// Starts counting from zero for a new run.
+    pub fn reset(&mut self) {
+        *self.usage.lock().unwrap() = RunUsage::default();
+        self.started = Instant::now();
+        self.previous = Duration::ZERO;
+    }
+
+    /// Carries on counting from what a run had used when it was checkpointed.
+    pub fn restore(&mut self, usage: UsageSummary, elapsed: Duration) {
+        *self.usage.lock().unwrap() = RunUsage { summary: usage, pending: vec![] };
+        self.started = Instant::now();
+        self.previous = elapsed;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.previous + self.started.elapsed()
+    }
+
+    /// Describes the first token, cost or time budget that ran out.
+    pub fn check_usage(&self) -> Option<String> {
+        let usage = self.usage.lock().unwrap().summary.total;
+
+        if let Some(max_tokens) = self.limits.max_tokens {
+            if usage.total_tokens() >= max_tokens {
+                return Some(format!("the budget of {max_tokens} tokens ran out"));
+            }
+        }
+
+        if let Some(max_cost) = self.limits.max_cost {
+            if usage.cost >= max_cost {
+                return Some(format!("the budget of ${max_cost:.2} ran out"));
+            }
+        }
+
+        if let Some(max_time) = self.limits.max_time {
+            if self.elapsed() >= Duration::from_secs(max_time) {
+                return Some(format!("the time limit of {max_time} seconds ran out"));
+            }
+        }
+
+        None
+    }
+
+    /// Like `check_usage`, but also checks how many decisions the dynamic agent has made.
+    pub fn check(&self, decisions: usize) -> Option<String> {
+        if let Some(max_decisions) = self.limits.max_decisions {
+            if decisions >= max_decisions {
+                return Some(format!("the limit of {max_decisions} decisions ran out"));
+            }
+        }
+
+        self.check_usage()
+    }
+}
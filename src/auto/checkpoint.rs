@@ -0,0 +1,181 @@
+use std::{collections::HashMap, error::Error, fmt::Display, fs, path::{Path, PathBuf}, time::Duration};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{CommandContext, Message, LLM, AgentInfo, UsageSummary};
+
+use super::{BrainThoughts, MethodicalPlan};
+
+#[derive(Debug, Clone)]
+pub struct NoCheckpointError(pub String);
+
+impl Display for NoCheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not find a checkpoint for the run '{}'", self.0)
+    }
+}
+
+impl Error for NoCheckpointError {}
+
+/// How far the static agent got through a subtask.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SubtaskProgress {
+    pub plan: MethodicalPlan,
+    pub completed_steps: usize,
+    pub saved_assets: usize
+}
+
+/// How far a run got, so it can be resumed without redoing finished work.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Progress {
+    /// The decision the dynamic agent is carrying out.
+    pub decision: Option<BrainThoughts>,
+    /// The response to the last decision that was carried out.
+    pub response: Option<String>,
+    pub subtask: Option<SubtaskProgress>,
+    /// How many decisions the dynamic agent has made, counted against the budget.
+    #[serde(default)] pub decisions: usize,
+    /// What the run had used when it was checkpointed, so a resumed run keeps its budget.
+    #[serde(default)] pub usage: UsageSummary,
+    /// In milliseconds.
+    #[serde(default)] pub elapsed: u64
+}
+
+pub struct RunState {
+    pub id: String,
+    pub task: String,
+    /// Where checkpoints are saved. Checkpointing is disabled when this is `None`.
+    pub checkpoint_dir: Option<PathBuf>,
+    pub progress: Progress,
+    /// Whether a task has run under this ID yet, so that another task gets its own.
+    pub started: bool
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LLMState {
+    pub prompt: Vec<Message>,
+    pub message_history: Vec<Message>,
+    pub end_prompt: Vec<Message>
+}
+
+impl LLMState {
+    pub fn from_llm(llm: &LLM) -> Self {
+        Self {
+            prompt: llm.prompt.clone(),
+            message_history: llm.message_history.clone(),
+            end_prompt: llm.end_prompt.clone()
+        }
+    }
+
+    pub fn apply(self, llm: &mut LLM) {
+        llm.prompt = self.prompt;
+        llm.message_history = self.message_history;
+        llm.end_prompt = self.end_prompt;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Checkpoint {
+    pub run_id: String,
+    pub task: String,
+    pub progress: Progress,
+    pub assets: HashMap<String, String>,
+    pub agents: HashMap<String, LLMState>
+}
+
+fn list_agents(context: &mut CommandContext) -> Vec<(&str, &mut AgentInfo)> {
+    let agents = &mut context.agents;
+
+    vec![
+        ("static", &mut agents.static_agent),
+        ("planner", &mut agents.planner),
+        ("dynamic", &mut agents.dynamic),
+        ("fast", &mut agents.fast)
+    ]
+}
+
+pub fn get_checkpoint_path(dir: &Path, run_id: &str) -> PathBuf {
+    dir.join(format!("{run_id}.json"))
+}
+
+pub fn create_checkpoint(context: &mut CommandContext) -> Checkpoint {
+    let agents = list_agents(context).into_iter()
+        .map(|(name, agent)| (name.to_string(), LLMState::from_llm(&agent.llm)))
+        .collect();
+
+    let progress = Progress {
+        usage: context.budget.usage.lock().unwrap().summary.clone(),
+        elapsed: context.budget.elapsed().as_millis() as u64,
+        ..context.run.progress.clone()
+    };
+
+    Checkpoint {
+        run_id: context.run.id.clone(),
+        task: context.run.task.clone(),
+        progress,
+        assets: context.assets.clone(),
+        agents
+    }
+}
+
+/// Saves the run's state, if checkpointing is enabled.
+pub fn save_checkpoint(context: &mut CommandContext) -> Result<(), Box<dyn Error>> {
+    let dir = match &context.run.checkpoint_dir {
+        Some(dir) => dir.clone(),
+        None => return Ok(())
+    };
+
+    let checkpoint = create_checkpoint(context);
+    let path = get_checkpoint_path(&dir, &checkpoint.run_id);
+
+    // Write to a temporary file first so a crash mid-write can't corrupt the last checkpoint.
+    fs::create_dir_all(&dir)?;
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string(&checkpoint)?)?;
+    fs::rename(&temp_path, &path)?;
+
+    Ok(())
+}
+
+pub fn load_checkpoint(context: &mut CommandContext, run_id: &str) -> Result<(), Box<dyn Error>> {
+    let dir = context.run.checkpoint_dir.clone()
+        .ok_or(NoCheckpointError(run_id.to_string()))?;
+
+    let path = get_checkpoint_path(&dir, run_id);
+    let checkpoint = fs::read_to_string(&path)
+        .map_err(|_| NoCheckpointError(run_id.to_string()))?;
+    let Checkpoint { run_id, task, progress, assets, mut agents } = serde_json::from_str(&checkpoint)?;
+
+    for (name, agent) in list_agents(context) {
+        if let Some(state) = agents.remove(name) {
+            state.apply(&mut agent.llm);
+        }
+    }
+
+    context.assets = assets;
+    context.budget.restore(progress.usage.clone(), Duration::from_millis(progress.elapsed));
+    context.run = RunState {
+        id: run_id,
+        task,
+        checkpoint_dir: Some(dir),
+        progress,
+        started: false
+    };
+
+    Ok(())
+}
+
+/// Removes the checkpoint of a run that finished, since there's nothing left to resume.
+pub fn clear_checkpoint(context: &mut CommandContext) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = &context.run.checkpoint_dir {
+        let path = get_checkpoint_path(dir, &context.run.id);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    context.run.progress = Progress::default();
+    context.budget.reset();
+
+    Ok(())
+}
@@ -6,7 +6,7 @@ use json5;
 use colored::Colorize;
 use serde_json::ser::PrettyFormatter;
 
-use crate::{LLM, SmartGPT};
+use crate::{LLM, SmartGPT, ListenToToken};
 
 use self::{agents::{processing::find_text_between_braces, worker::run_worker}};
 
@@ -14,12 +14,22 @@ mod agents;
 mod run;
 mod responses;
 mod classify;
+mod assistant;
+mod approval;
+mod checkpoint;
+mod budget;
+mod schema;
 
 pub use run::{Action};
+pub use assistant::*;
+pub use approval::*;
+pub use checkpoint::*;
+pub use budget::*;
+pub use schema::*;
 pub use agents::worker::*;
 
 #[derive(Debug)]
-pub struct DisallowedAction(Box<dyn Error>);
+pub struct DisallowedAction(pub Box<dyn Error>);
 
 impl Error for DisallowedAction {}
 impl Display for DisallowedAction {
@@ -66,9 +76,64 @@ pub fn try_parse_yaml<T : DeserializeOwned>(llm: &LLM, tries: usize, max_tokens:
     try_parse_base(llm, tries, max_tokens, temperature, "yml", |str| serde_yaml::from_str(str).map_err(|el| Box::new(el) as Box<dyn Error>))
 }
 
-pub fn try_parse_json<T : DeserializeOwned + Serialize>(llm: &LLM, tries: usize, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<ParsedResponse<T>, Box<dyn Error>> {
+/// Gets a response matching `T`, using the model's structured output when it has it,
+/// and pulling the JSON out of a text response otherwise.
+pub fn try_parse_json<T : DeserializeOwned + Serialize + JsonSchema>(llm: &LLM, tries: usize, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<ParsedResponse<T>, Box<dyn Error>> {
+    try_parse_json_streaming(llm, tries, max_tokens, temperature, &mut |_| Ok(()))
+}
+
+/// Like `try_parse_json`, but streams text responses to `on_token` as they're generated.
+pub fn try_parse_json_streaming<T : DeserializeOwned + Serialize + JsonSchema>(
+    llm: &LLM, tries: usize, max_tokens: Option<u16>, temperature: Option<f32>,
+    on_token: &mut ListenToToken<'_>
+) -> Result<ParsedResponse<T>, Box<dyn Error>> {
+    with_escalation(llm, || parse_json(llm, tries, max_tokens, temperature, on_token))
+}
+
+/// Retries `parse` with the next model in the agent's fallback chain for as long as its responses can't be parsed.
+fn with_escalation<T>(llm: &LLM, mut parse: impl FnMut() -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+    let result = loop {
+        match parse() {
+            Err(err) if err.is::<CannotParseError>() && llm.model.escalate() => {
+                eprintln!("{}", "Escalating to the next model.".yellow());
+            },
+            result => break result
+        }
+    };
+
+    llm.model.reset_escalation();
+    result
+}
+
+fn parse_json<T : DeserializeOwned + Serialize + JsonSchema>(
+    llm: &LLM, tries: usize, max_tokens: Option<u16>, temperature: Option<f32>,
+    on_token: &mut ListenToToken<'_>
+) -> Result<ParsedResponse<T>, Box<dyn Error>> {
+    let schema = get_response_schema::<T>();
+    let mut structured = true;
+
     for i in 0..tries {
-        let response = llm.model.get_response_sync(&llm.get_messages(), max_tokens, temperature)?;
+        let messages = llm.get_messages();
+
+        let structured_response = if structured {
+            match llm.model.get_structured_response_sync(&messages, &schema, max_tokens, temperature) {
+                Ok(response) => response,
+                Err(err) => {
+                    eprintln!("{}", format!("Structured output failed, falling back to text: {err}").red());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let response = match structured_response {
+            Some(response) => response,
+            None => {
+                structured = false;
+                llm.model.get_response_streaming_sync(&messages, max_tokens, temperature, on_token)?
+            }
+        };
         let processed_response = find_text_between_braces(&response).unwrap_or("None".to_string());
 
         let formatter = PrettyFormatter::with_indent(b"\t");
@@ -89,9 +154,10 @@ pub fn try_parse_json<T : DeserializeOwned + Serialize>(llm: &LLM, tries: usize,
                 })
             },
             Err(err) => {
-                println!("{}", format!("Try {} failed.", i + 1).red());
-                println!("{response}");
-                println!("{err}");
+                llm.model.discard_last_response();
+                eprintln!("{}", format!("Try {} failed.", i + 1).red());
+                eprintln!("{response}");
+                eprintln!("{err}");
             }
         }
     }
@@ -99,7 +165,11 @@ pub fn try_parse_json<T : DeserializeOwned + Serialize>(llm: &LLM, tries: usize,
     Err(Box::new(CannotParseError))
 }
 
-pub fn try_parse_base<T : DeserializeOwned>(llm: &LLM, tries: usize, max_tokens: Option<u16>, temperature: Option<f32>, lang: &str, parse: impl Fn(&str) -> Result<T, Box<dyn Error>>) -> Result<ParsedResponse<T>, Box<dyn Error>> {
+pub fn try_parse_base<T>(llm: &LLM, tries: usize, max_tokens: Option<u16>, temperature: Option<f32>, lang: &str, parse: impl Fn(&str) -> Result<T, Box<dyn Error>>) -> Result<ParsedResponse<T>, Box<dyn Error>> {
+    with_escalation(llm, || parse_base(llm, tries, max_tokens, temperature, lang, &parse))
+}
+
+fn parse_base<T>(llm: &LLM, tries: usize, max_tokens: Option<u16>, temperature: Option<f32>, lang: &str, parse: &impl Fn(&str) -> Result<T, Box<dyn Error>>) -> Result<ParsedResponse<T>, Box<dyn Error>> {
     for i in 0..tries {
         let response = llm.model.get_response_sync(&llm.get_messages(), max_tokens, temperature)?;
         let processed_response = response.trim();
@@ -107,7 +177,7 @@ pub fn try_parse_base<T : DeserializeOwned>(llm: &LLM, tries: usize, max_tokens:
             .unwrap_or(&processed_response)
             .to_string();
         let processed_response = processed_response.strip_prefix(&format!("{lang}"))
-            .unwrap_or(&response)
+            .unwrap_or(&processed_response)
             .to_string();
         let processed_response = processed_response.strip_suffix("```")
             .unwrap_or(&processed_response)
@@ -121,9 +191,10 @@ pub fn try_parse_base<T : DeserializeOwned>(llm: &LLM, tries: usize, max_tokens:
                 })
             },
             Err(err) => {
-                println!("{}", format!("Try {} failed.", i + 1).red());
-                println!("{response}");
-                println!("{err}");
+                llm.model.discard_last_response();
+                eprintln!("{}", format!("Try {} failed.", i + 1).red());
+                eprintln!("{response}");
+                eprintln!("{err}");
             }
         }
     }
@@ -0,0 +1,158 @@
+use serde_json::{Value, json, Map};
+
+use crate::ResponseSchema;
+
+use super::{classify::Classification, BrainThoughts, DynamicPlan, MethodicalPlan, MethodicalThoughts, Memories, Reflections};
+
+/// The JSON schema of a type the agents parse from LLM responses, so providers
+/// that support structured output can be held to it.
+pub trait JsonSchema {
+    /// Used as the function name, so it may only have letters, digits, `_` and `-`.
+    const NAME: &'static str;
+
+    fn json_schema() -> Value;
+}
+
+pub fn get_response_schema<T : JsonSchema>() -> ResponseSchema {
+    ResponseSchema {
+        name: T::NAME.to_string(),
+        schema: T::json_schema()
+    }
+}
+
+pub fn string_schema() -> Value {
+    json!({ "type": "string" })
+}
+
+pub fn array_schema(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// Matches anything, for arguments that depend on the tool or decision.
+pub fn any_schema() -> Value {
+    json!({})
+}
+
+pub fn object_schema(properties: Vec<(&str, Value)>, required: &[&str]) -> Value {
+    let properties = properties.into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect::<Map<_, _>>();
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required
+    })
+}
+
+impl JsonSchema for BrainThoughts {
+    const NAME: &'static str = "decide";
+
+    fn json_schema() -> Value {
+        object_schema(vec![
+            ("thoughts", string_schema()),
+            ("reasoning", string_schema()),
+            ("decision", object_schema(vec![
+                ("type", json!({
+                    "type": "string",
+                    "enum": [ "spawn_agent", "brainstorm", "final_response" ]
+                })),
+                ("args", any_schema())
+            ], &[ "type", "args" ]))
+        ], &[ "thoughts", "reasoning", "decision" ])
+    }
+}
+
+impl JsonSchema for DynamicPlan {
+    const NAME: &'static str = "plan";
+
+    fn json_schema() -> Value {
+        let field = "concise plan on how you will complete the task";
+        object_schema(vec![ (field, string_schema()) ], &[ field ])
+    }
+}
+
+impl JsonSchema for MethodicalPlan {
+    const NAME: &'static str = "plan_steps";
+
+    fn json_schema() -> Value {
+        let resource = object_schema(vec![
+            ("name", string_schema()),
+            ("question", string_schema())
+        ], &[ "name" ]);
+        let action = object_schema(vec![
+            ("name", string_schema()),
+            ("purpose", string_schema())
+        ], &[ "name" ]);
+
+        let step = object_schema(vec![
+            ("idea", string_schema()),
+            ("decision", json!({
+                "oneOf": [
+                    object_schema(vec![ ("resource", resource) ], &[ "resource" ]),
+                    object_schema(vec![ ("action", action) ], &[ "action" ])
+                ]
+            }))
+        ], &[ "idea", "decision" ]);
+
+        let asset = object_schema(vec![
+            ("name", string_schema()),
+            ("description", string_schema())
+        ], &[ "name", "description" ]);
+
+        object_schema(vec![
+            ("thoughts", string_schema()),
+            ("steps", array_schema(step)),
+            ("assets", array_schema(asset))
+        ], &[ "thoughts", "steps", "assets" ])
+    }
+}
+
+impl JsonSchema for MethodicalThoughts {
+    const NAME: &'static str = "run_step";
+
+    fn json_schema() -> Value {
+        object_schema(vec![
+            ("thoughts", string_schema()),
+            ("action", object_schema(vec![
+                ("tool", string_schema()),
+                ("args", any_schema())
+            ], &[ "tool" ]))
+        ], &[ "thoughts", "action" ])
+    }
+}
+
+impl JsonSchema for Memories {
+    const NAME: &'static str = "save_memories";
+
+    fn json_schema() -> Value {
+        object_schema(vec![
+            ("actions", array_schema(string_schema())),
+            ("observations", array_schema(string_schema()))
+        ], &[ "actions", "observations" ])
+    }
+}
+
+impl JsonSchema for Reflections {
+    const NAME: &'static str = "reflect";
+
+    fn json_schema() -> Value {
+        object_schema(vec![
+            ("insights", array_schema(string_schema()))
+        ], &[ "insights" ])
+    }
+}
+
+impl JsonSchema for Classification {
+    const NAME: &'static str = "classify";
+
+    fn json_schema() -> Value {
+        object_schema(vec![
+            ("thoughts on how to classify it", string_schema()),
+            ("message classification", json!({
+                "type": "string",
+                "enum": [ "conversational", "task" ]
+            }))
+        ], &[ "thoughts on how to classify it", "message classification" ])
+    }
+}
@@ -0,0 +1,29 @@
+use std::{error::Error, sync::Arc};
+
+use async_trait::async_trait;
+use serde_json::{Value, json};
+
+use crate::{EmbeddingModel, DiskCache, hash_key};
+
+/// Wraps an embedding model to keep the embeddings of texts it has seen on disk.
+pub struct CachedEmbeddings {
+    pub model: Box<dyn EmbeddingModel>,
+    /// What tells this model's embeddings apart from other models', usually its config.
+    pub identity: Value,
+    pub cache: Arc<DiskCache>
+}
+
+#[async_trait]
+impl EmbeddingModel for CachedEmbeddings {
+    async fn get_embedding(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let key = hash_key(&json!({ "model": self.identity, "text": text }))?;
+        if let Some(embedding) = self.cache.get(&key) {
+            return Ok(embedding);
+        }
+
+        let embedding = self.model.get_embedding(text).await?;
+        self.cache.store(&key, &embedding);
+
+        Ok(embedding)
+    }
+}
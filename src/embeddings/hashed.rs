@@ -0,0 +1,79 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{EmbeddingModel, EmbeddingProvider};
+
+/// FNV-1a, which unlike `DefaultHasher` gives the same hash on every Rust release, so stored
+/// embeddings keep matching new ones.
+fn hash_word(word: &str) -> u64 {
+    word.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Embeds text by hashing its words into buckets, so similar texts get similar embeddings
+/// without a model.
+pub fn hash_embedding(text: &str, dimensions: usize) -> Vec<f32> {
+    let mut embedding = vec![0.; dimensions.max(1)];
+
+    for word in text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+        let hash = hash_word(&word.to_lowercase());
+
+        let bucket = (hash % embedding.len() as u64) as usize;
+        // Another bit of the hash picks a sign, so unrelated words tend to cancel out.
+        embedding[bucket] += if hash & (1 << 63) == 0 { 1. } else { -1. };
+    }
+
+    let length = embedding.iter().map(|el| el * el).sum::<f32>().sqrt();
+    if length > 0. {
+        for el in &mut embedding {
+            *el /= length;
+        }
+    }
+
+    embedding
+}
+
+/// A bag-of-words embedding that works offline. It only matches texts that share words.
+pub struct HashedEmbeddings {
+    pub dimensions: usize
+}
+
+#[async_trait]
+impl EmbeddingModel for HashedEmbeddings {
+    async fn get_embedding(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        Ok(hash_embedding(text, self.dimensions))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HashedEmbeddingsConfig {
+    pub dimensions: Option<usize>
+}
+
+pub struct HashedEmbeddingProvider;
+
+impl EmbeddingProvider for HashedEmbeddingProvider {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn get_name(&self) -> &str {
+        "hashed"
+    }
+
+    fn create(&self, value: Value) -> Result<Box<dyn EmbeddingModel>, Box<dyn Error>> {
+        let config: HashedEmbeddingsConfig = serde_json::from_value(value)?;
+
+        Ok(Box::new(HashedEmbeddings {
+            dimensions: config.dimensions.unwrap_or(256)
+        }))
+    }
+}
+
+pub fn create_embedding_hashed() -> Box<dyn EmbeddingProvider> {
+    Box::new(HashedEmbeddingProvider)
+}
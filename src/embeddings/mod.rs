@@ -0,0 +1,24 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+mod openai;
+mod hashed;
+mod cached;
+
+pub use openai::*;
+pub use hashed::*;
+pub use cached::*;
+
+/// Embeds text for memory systems, separately from the model an agent chats with.
+#[async_trait]
+pub trait EmbeddingModel : Send + Sync {
+    async fn get_embedding(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>>;
+}
+
+pub trait EmbeddingProvider {
+    fn is_enabled(&self) -> bool;
+    fn get_name(&self) -> &str;
+    fn create(&self, value: Value) -> Result<Box<dyn EmbeddingModel>, Box<dyn Error>>;
+}
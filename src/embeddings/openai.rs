@@ -0,0 +1,59 @@
+use std::error::Error;
+
+use async_openai::{Client, types::{CreateEmbeddingRequest, EmbeddingInput}};
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{EmbeddingModel, EmbeddingProvider};
+
+pub struct OpenAIEmbeddings {
+    pub model: String,
+    pub client: Client
+}
+
+#[async_trait]
+impl EmbeddingModel for OpenAIEmbeddings {
+    async fn get_embedding(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let embeddings = self.client.embeddings().create(CreateEmbeddingRequest {
+            model: self.model.clone(),
+            user: None,
+            input: EmbeddingInput::String(text.to_string())
+        }).await?;
+
+        Ok(embeddings.data[0].embedding.clone())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OpenAIEmbeddingsConfig {
+    #[serde(rename = "api key")] pub api_key: String,
+    pub model: Option<String>,
+    #[serde(rename = "api base")] pub api_base: Option<String>
+}
+
+pub struct OpenAIEmbeddingProvider;
+
+impl EmbeddingProvider for OpenAIEmbeddingProvider {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn get_name(&self) -> &str {
+        "openai"
+    }
+
+    fn create(&self, value: Value) -> Result<Box<dyn EmbeddingModel>, Box<dyn Error>> {
+        let config: OpenAIEmbeddingsConfig = serde_json::from_value(value)?;
+        let api_base = config.api_base.unwrap_or("https://api.openai.com/v1".to_string());
+
+        Ok(Box::new(OpenAIEmbeddings {
+            model: config.model.unwrap_or("text-embedding-ada-002".to_string()),
+            client: Client::new().with_api_base(api_base).with_api_key(config.api_key)
+        }))
+    }
+}
+
+pub fn create_embedding_openai() -> Box<dyn EmbeddingProvider> {
+    Box::new(OpenAIEmbeddingProvider)
+}
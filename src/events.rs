@@ -0,0 +1,122 @@
+use std::{error::Error, fs::{File, OpenOptions}, io::{self, Write}, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{Update, log_update};
+
+/// Bumped whenever the shape of `UpdateEvent` changes in a way consumers would notice.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpdateEvent {
+    pub version: u32,
+    pub run_id: String,
+    pub sequence: usize,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub update: Update
+}
+
+pub fn create_run_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+pub fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|el| el.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Formats an event as one Server-Sent Events message.
+pub fn format_sse(event: &UpdateEvent) -> Result<String, Box<dyn Error>> {
+    Ok(format!(
+        "id: {}\nevent: update\ndata: {}\n\n",
+        event.sequence,
+        serde_json::to_string(event)?
+    ))
+}
+
+pub trait UpdateSink : Send {
+    fn send(&mut self, event: &UpdateEvent) -> Result<(), Box<dyn Error>>;
+}
+
+/// Prints updates to the terminal with `log_update`.
+pub struct LogSink;
+
+impl UpdateSink for LogSink {
+    fn send(&mut self, event: &UpdateEvent) -> Result<(), Box<dyn Error>> {
+        log_update(&event.update)
+    }
+}
+
+/// Writes every event as one line of JSON.
+pub struct JsonLinesSink<W : Write + Send> {
+    pub writer: W
+}
+
+impl JsonLinesSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self { writer: io::stdout() }
+    }
+}
+
+impl JsonLinesSink<File> {
+    pub fn file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        Ok(Self { writer: file })
+    }
+}
+
+impl<W : Write + Send> UpdateSink for JsonLinesSink<W> {
+    fn send(&mut self, event: &UpdateEvent) -> Result<(), Box<dyn Error>> {
+        writeln!(self.writer, "{}", serde_json::to_string(event)?)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Wraps updates from one run into events and hands them to every sink.
+pub struct UpdateEmitter {
+    pub run_id: String,
+    pub sequence: usize,
+    pub sinks: Vec<Box<dyn UpdateSink>>
+}
+
+impl UpdateEmitter {
+    pub fn new(run_id: &str, sinks: Vec<Box<dyn UpdateSink>>) -> Self {
+        Self {
+            run_id: run_id.to_string(),
+            sequence: 0,
+            sinks
+        }
+    }
+
+    pub fn emit(&mut self, update: &Update) -> Result<(), Box<dyn Error>> {
+        // Events from the new run are numbered from the start again.
+        if let Update::NewRun(run_id) = update {
+            self.run_id = run_id.clone();
+            self.sequence = 0;
+        }
+
+        let event = UpdateEvent {
+            version: EVENT_SCHEMA_VERSION,
+            run_id: self.run_id.clone(),
+            sequence: self.sequence,
+            timestamp: get_timestamp(),
+            update: update.clone()
+        };
+        self.sequence += 1;
+
+        for sink in &mut self.sinks {
+            sink.send(&event)?;
+        }
+
+        Ok(())
+    }
+}
@@ -3,19 +3,25 @@ mod plugins;
 mod tools;
 mod chunk;
 mod llms;
+mod embeddings;
 mod api;
 mod runner;
 mod memory;
 mod auto;
 mod log;
+mod events;
+mod server;
 
 pub use plugin::*;
 pub use plugins::*;
 pub use tools::*;
 pub use chunk::*;
 pub use llms::*;
+pub use embeddings::*;
 pub use api::*;
 pub use runner::*;
 pub use memory::*;
 pub use auto::*;
-pub use log::*;
\ No newline at end of file
+pub use log::*;
+pub use events::*;
+pub use server::*;
\ No newline at end of file
@@ -0,0 +1,183 @@
+use std::{error::Error, fmt::Display};
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+
+use crate::{LLMProvider, Message, LLMModel, TokenUsage, check_status, NoEmbeddingsError, estimate_token_count, estimate_tokens_from_text};
+
+#[derive(Debug, Clone)]
+pub struct AnthropicError(pub String);
+
+impl Display for AnthropicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the Anthropic API failed: {}", self.0)
+    }
+}
+
+impl Error for AnthropicError {}
+
+pub struct Anthropic {
+    pub model: String,
+    pub api_key: String,
+    pub api_base: String,
+    pub version: String,
+    pub max_tokens: u16,
+    pub context_tokens: usize,
+    pub client: reqwest::Client
+}
+
+/// Splits messages into the system prompt and turns that alternate between the user
+/// and assistant, merging back-to-back messages from the same role, since the Messages API
+/// requires it. Assistant messages before the first user message are treated as instructions.
+pub fn create_anthropic_messages(messages: &[Message]) -> (String, Vec<Value>) {
+    let mut system: Vec<&str> = vec![];
+    let mut turns: Vec<(&str, String)> = vec![];
+
+    for message in messages {
+        let role = match message {
+            Message::System(_) => {
+                system.push(message.content());
+                continue;
+            },
+            Message::Assistant(_) if turns.is_empty() => {
+                system.push(message.content());
+                continue;
+            },
+            Message::User(_) => "user",
+            Message::Assistant(_) => "assistant"
+        };
+
+        match turns.last_mut() {
+            Some((last_role, content)) if *last_role == role => {
+                content.push_str("\n\n");
+                content.push_str(message.content());
+            },
+            _ => turns.push((role, message.content().to_string()))
+        }
+    }
+
+    let turns = turns.into_iter()
+        .map(|(role, content)| json!({ "role": role, "content": content }))
+        .collect();
+
+    (system.join("\n\n"), turns)
+}
+
+#[async_trait]
+impl LLMModel for Anthropic {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let (system, messages) = create_anthropic_messages(messages);
+
+        let mut request = json!({
+            "model": self.model,
+            "max_tokens": max_tokens.unwrap_or(self.max_tokens),
+            "messages": messages
+        });
+        if !system.is_empty() {
+            request["system"] = json!(system);
+        }
+        if let Some(temperature) = temperature {
+            // Anthropic's temperatures only go up to 1.
+            request["temperature"] = json!(temperature.min(1.));
+        }
+
+        let response: Value = check_status(self.client
+            .post(format!("{}/messages", self.api_base))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", &self.version)
+            .json(&request)
+            .send()
+            .await?).await?
+            .json()
+            .await?;
+
+        if let Some(error) = response.get("error") {
+            return Err(Box::new(AnthropicError(error["message"].as_str().unwrap_or("unknown error").to_string())));
+        }
+
+        let content = response["content"].as_array()
+            .ok_or(AnthropicError("the response had no content".to_string()))?
+            .iter()
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("");
+
+        let usage = response["usage"]["input_tokens"].as_u64()
+            .zip(response["usage"]["output_tokens"].as_u64())
+            .map(|(prompt_tokens, completion_tokens)| TokenUsage {
+                prompt_tokens: prompt_tokens as usize,
+                completion_tokens: completion_tokens as usize
+            });
+
+        Ok((content, usage))
+    }
+
+    /// Anthropic has no embeddings API.
+    async fn get_base_embed(&self, _text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        Err(Box::new(NoEmbeddingsError("Anthropic".to_string())))
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(estimate_token_count(messages))
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.context_tokens
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.context_tokens.saturating_sub(self.get_token_count(messages)?))
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        estimate_tokens_from_text(text)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AnthropicConfig {
+    #[serde(rename = "api key")] pub api_key: String,
+    pub model: Option<String>,
+    #[serde(rename = "api base")] pub api_base: Option<String>,
+    /// The `anthropic-version` header.
+    pub version: Option<String>,
+    /// Used when an agent doesn't ask for a limit, since Anthropic requires one.
+    #[serde(rename = "max tokens")] pub max_tokens: Option<u16>,
+    #[serde(rename = "context tokens")] pub context_tokens: Option<usize>
+}
+
+pub struct AnthropicProvider;
+
+#[async_trait]
+impl LLMProvider for AnthropicProvider {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn get_name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn create(&self, value: Value) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
+        let config: AnthropicConfig = serde_json::from_value(value)?;
+
+        Ok(Box::new(Anthropic {
+            model: config.model.unwrap_or("claude-3-haiku-20240307".to_string()),
+            api_key: config.api_key,
+            api_base: config.api_base.unwrap_or("https://api.anthropic.com/v1".to_string()),
+            version: config.version.unwrap_or("2023-06-01".to_string()),
+            max_tokens: config.max_tokens.unwrap_or(1024),
+            context_tokens: config.context_tokens.unwrap_or(200000),
+            client: reqwest::Client::new()
+        }))
+    }
+}
+
+pub fn create_model_anthropic() -> Box<dyn LLMProvider> {
+    Box::new(AnthropicProvider)
+}
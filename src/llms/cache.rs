@@ -0,0 +1,320 @@
+use std::{error::Error, fs, path::{Path, PathBuf}, collections::{HashMap, VecDeque}, sync::{Mutex, Arc}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use sha2::{Sha256, Digest};
+
+use crate::{LLMModel, Message, ResponseSchema, TokenUsage, OnToken};
+
+/// Where one cache is kept, and how much it can hold.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheOptions {
+    pub directory: String,
+    /// In seconds. Entries never expire without one.
+    #[serde(default)] pub ttl: Option<f64>,
+    /// In megabytes. The oldest entries are removed once the cache grows past it.
+    #[serde(rename = "max size", default)] pub max_size: Option<f64>
+}
+
+/// Caches are only used when they're configured.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default)] pub responses: Option<CacheOptions>,
+    #[serde(default)] pub embeddings: Option<CacheOptions>
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+    /// Seconds since the Unix epoch.
+    created: u64,
+    value: T
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|el| el.as_secs()).unwrap_or(0)
+}
+
+/// Hashes anything serializable into a key that's safe to use as a file name.
+pub fn hash_key(value: &impl Serialize) -> Result<String, Box<dyn Error>> {
+    let digest = Sha256::digest(serde_json::to_vec(value)?);
+    Ok(format!("{digest:x}"))
+}
+
+/// The entries of a cache, oldest first, so it can be kept under its size limit without
+/// looking at every file each time an entry is written.
+#[derive(Default)]
+struct CacheState {
+    size: u64,
+    /// The size of each entry, and when it was written, counted in writes.
+    entries: HashMap<PathBuf, (u64, u64)>,
+    /// Entries that were written again or removed since are skipped.
+    order: VecDeque<(PathBuf, u64)>,
+    writes: u64
+}
+
+impl CacheState {
+    fn load(directory: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut files = vec![];
+        for file in fs::read_dir(directory)? {
+            let file = file?;
+            let metadata = file.metadata()?;
+            if metadata.is_file() {
+                files.push((metadata.modified().unwrap_or(UNIX_EPOCH), metadata.len(), file.path()));
+            }
+        }
+        files.sort_by_key(|(modified, _, _)| *modified);
+
+        let mut state = Self::default();
+        for (_, len, path) in files {
+            state.add(path, len);
+        }
+
+        Ok(state)
+    }
+
+    fn add(&mut self, path: PathBuf, len: u64) {
+        self.remove(&path);
+
+        self.writes += 1;
+        self.size += len;
+        self.entries.insert(path.clone(), (len, self.writes));
+        self.order.push_back((path, self.writes));
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some((len, _)) = self.entries.remove(path) {
+            self.size -= len;
+        }
+    }
+
+    /// Takes out the oldest entry that's still in the cache.
+    fn pop_oldest(&mut self) -> Option<PathBuf> {
+        while let Some((path, written)) = self.order.pop_front() {
+            if self.entries.get(&path).map(|(_, el)| *el == written).unwrap_or(false) {
+                self.remove(&path);
+                return Some(path);
+            }
+        }
+
+        None
+    }
+}
+
+/// A cache with one JSON file per entry.
+pub struct DiskCache {
+    pub directory: PathBuf,
+    pub ttl: Option<Duration>,
+    pub max_size: Option<u64>,
+    state: Mutex<CacheState>
+}
+
+impl DiskCache {
+    pub fn new(options: &CacheOptions) -> Result<Self, Box<dyn Error>> {
+        let directory = PathBuf::from(&options.directory);
+        fs::create_dir_all(&directory)?;
+
+        let max_size = options.max_size
+            .filter(|megabytes| megabytes.is_finite() && *megabytes >= 0.)
+            .map(|megabytes| (megabytes * 1_000_000.) as u64);
+
+        // The size only matters with a limit, so the directory is only read for one.
+        let state = match max_size {
+            Some(_) => CacheState::load(&directory)?,
+            None => CacheState::default()
+        };
+
+        Ok(Self {
+            directory,
+            ttl: options.ttl
+                .filter(|seconds| seconds.is_finite() && *seconds >= 0.)
+                .map(Duration::from_secs_f64),
+            max_size,
+            state: Mutex::new(state)
+        })
+    }
+
+    fn get_path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{key}.json"))
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let path = self.get_path(key);
+        let entry: CacheEntry<T> = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+
+        if let Some(ttl) = self.ttl {
+            if now().saturating_sub(entry.created) > ttl.as_secs() {
+                self.state.lock().unwrap().remove(&path);
+                let _ = fs::remove_file(path);
+                return None;
+            }
+        }
+
+        Some(entry.value)
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        let entry = serde_json::to_vec(&CacheEntry { created: now(), value })?;
+        let path = self.get_path(key);
+        fs::write(&path, &entry)?;
+        state.add(path, entry.len() as u64);
+
+        self.prune(&mut state)
+    }
+
+    /// Like `set`, but a failed write is only logged, since whatever was being cached is still good.
+    pub fn store<T: Serialize>(&self, key: &str, value: &T) {
+        if let Err(err) = self.set(key, value) {
+            eprintln!("Could not write to the cache in {}: {err}", self.directory.display());
+        }
+    }
+
+    pub fn remove(&self, key: &str) {
+        let path = self.get_path(key);
+        self.state.lock().unwrap().remove(&path);
+        let _ = fs::remove_file(path);
+    }
+
+    /// Removes the oldest entries until the cache fits in its size limit.
+    fn prune(&self, state: &mut CacheState) -> Result<(), Box<dyn Error>> {
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+
+        while state.size > max_size {
+            let Some(path) = state.pop_oldest() else {
+                break;
+            };
+
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(Box::new(err)),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Wraps a model to answer repeated requests from disk. Cached responses aren't metered,
+/// since they cost nothing.
+pub struct CachedModel {
+    pub model: Box<dyn LLMModel>,
+    /// What tells this model's responses apart from other models', usually its config.
+    pub identity: Value,
+    pub responses: Option<Arc<DiskCache>>,
+    pub embeddings: Option<Arc<DiskCache>>,
+    /// The key of the last response given, so it can be discarded.
+    pub last_key: Mutex<Option<String>>
+}
+
+impl CachedModel {
+    fn get_response_key(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, schema: Option<&ResponseSchema>) -> Result<String, Box<dyn Error>> {
+        hash_key(&json!({
+            "model": self.identity,
+            "messages": messages,
+            "max tokens": max_tokens,
+            "temperature": temperature,
+            "schema": schema.map(|schema| &schema.schema)
+        }))
+    }
+
+    fn get_cached(&self, key: &str) -> Option<String> {
+        let response = self.responses.as_ref()?.get(key)?;
+        *self.last_key.lock().unwrap() = Some(key.to_string());
+        Some(response)
+    }
+
+    fn cache(&self, key: &str, response: &str) {
+        if let Some(cache) = &self.responses {
+            cache.store(key, &response);
+            *self.last_key.lock().unwrap() = Some(key.to_string());
+        }
+    }
+}
+
+#[async_trait]
+impl LLMModel for CachedModel {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let key = self.get_response_key(messages, max_tokens, temperature, None)?;
+        if let Some(response) = self.get_cached(&key) {
+            return Ok((response, None));
+        }
+
+        let (response, usage) = self.model.get_response_with_usage(messages, max_tokens, temperature).await?;
+        self.cache(&key, &response);
+
+        Ok((response, usage))
+    }
+
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let key = self.get_response_key(messages, max_tokens, temperature, None)?;
+        if let Some(response) = self.get_cached(&key) {
+            on_token(&response);
+            return Ok((response, None));
+        }
+
+        let (response, usage) = self.model.get_response_streaming(messages, max_tokens, temperature, on_token).await?;
+        self.cache(&key, &response);
+
+        Ok((response, usage))
+    }
+
+    async fn get_structured_response(&self, messages: &[Message], schema: &ResponseSchema, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<Option<(String, Option<TokenUsage>)>, Box<dyn Error>> {
+        let key = self.get_response_key(messages, max_tokens, temperature, Some(schema))?;
+        if let Some(response) = self.get_cached(&key) {
+            return Ok(Some((response, None)));
+        }
+
+        let response = self.model.get_structured_response(messages, schema, max_tokens, temperature).await?;
+        if let Some((response, _)) = &response {
+            self.cache(&key, response);
+        }
+
+        Ok(response)
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let Some(cache) = &self.embeddings else {
+            return self.model.get_base_embed(text).await;
+        };
+
+        let key = hash_key(&json!({ "model": self.identity, "text": text }))?;
+        if let Some(embedding) = cache.get(&key) {
+            return Ok(embedding);
+        }
+
+        let embedding = self.model.get_base_embed(text).await?;
+        cache.store(&key, &embedding);
+
+        Ok(embedding)
+    }
+
+    fn discard_last_response(&self) {
+        if let (Some(cache), Some(key)) = (&self.responses, self.last_key.lock().unwrap().take()) {
+            cache.remove(&key);
+        }
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_token_count(messages)
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.model.get_token_limit()
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_tokens_remaining(messages)
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.model.get_tokens_from_text(text)
+    }
+}
@@ -1,22 +1,47 @@
-use std::error::Error;
+use std::{error::Error, fmt::Display};
 
 use async_openai::{Client, types::{CreateChatCompletionResponse, CreateChatCompletionRequest, ChatCompletionRequestMessage, Role, CreateEmbeddingRequest, EmbeddingInput}};
 use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
-use serde_json::Value;
+use serde_json::{Value, json};
 use tiktoken_rs::{async_openai::{get_chat_completion_max_tokens, num_tokens_from_messages}, model::get_context_size, cl100k_base, r50k_base};
 
-use crate::{LLMProvider, Message, LLMModel};
+use crate::{LLMProvider, Message, LLMModel, TokenUsage, ResponseSchema, OnToken, check_status};
+
+#[derive(Debug, Clone)]
+pub struct NoStructuredResponseError(pub String);
+
+impl Display for NoStructuredResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the structured response had no {}", self.0)
+    }
+}
+
+impl Error for NoStructuredResponseError {}
+
+/// How a `ChatGPT` model is held to the schema of structured responses.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StructuredOutput {
+    /// Asks for JSON in the prompt and parses it out of the text.
+    #[default]
+    #[serde(rename = "none")] None,
+    /// Forces a call to a function that takes the schema as its parameters.
+    #[serde(rename = "functions")] Functions,
+    /// Sets the response format to the schema.
+    #[serde(rename = "json schema")] JsonSchema
+}
 
 pub struct ChatGPT {
     pub model: String,
     pub embedding_model: String,
+    pub api_key: String,
+    pub api_base: String,
+    pub structured_output: StructuredOutput,
     pub client: Client
 }
 
-#[async_trait]
-impl LLMModel for ChatGPT {
-    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+impl ChatGPT {
+    pub fn create_request(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> CreateChatCompletionRequest {
         let mut request = CreateChatCompletionRequest::default();
 
         request.model = self.model.clone();
@@ -28,13 +53,129 @@ impl LLMModel for ChatGPT {
         request.temperature = temperature;
 
         request.max_tokens = max_tokens;
-        
-        let response: CreateChatCompletionResponse = self.client
-            .chat()
-            .create(request.clone())
+
+        request
+    }
+}
+
+#[async_trait]
+impl LLMModel for ChatGPT {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let request = self.create_request(messages, max_tokens, temperature);
+
+        // Sent by hand rather than with async-openai, which retries rate limits on its own without
+        // a limit or Retry-After, so `RetryModel` can handle them instead.
+        let response: CreateChatCompletionResponse = check_status(reqwest::Client::new()
+            .post(format!("{}/chat/completions", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()
+            .await?).await?
+            .json()
+            .await?;
+
+        let usage = response.usage.map(|usage| TokenUsage {
+            prompt_tokens: usage.prompt_tokens as usize,
+            completion_tokens: usage.completion_tokens as usize
+        });
+
+        Ok((response.choices[0].message.content.clone(), usage))
+    }
+
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let mut request = serde_json::to_value(self.create_request(messages, max_tokens, temperature))?;
+        request["stream"] = json!(true);
+        request["stream_options"] = json!({ "include_usage": true });
+
+        let mut response = check_status(reqwest::Client::new()
+            .post(format!("{}/chat/completions", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()
+            .await?).await?;
+
+        let mut content = String::new();
+        let mut usage: Option<TokenUsage> = None;
+        let mut buffer: Vec<u8> = vec![];
+
+        // Server-sent events, one `data: {...}` line per chunk, until `data: [DONE]`.
+        'stream: while let Some(chunk) = response.chunk().await? {
+            buffer.extend_from_slice(&chunk);
+
+            while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let data = match line.trim().strip_prefix("data:") {
+                    Some(data) => data.trim(),
+                    None => continue
+                };
+
+                if data == "[DONE]" {
+                    break 'stream;
+                }
+
+                let event: Value = serde_json::from_str(data)?;
+                if let Some(token) = event["choices"][0]["delta"]["content"].as_str() {
+                    content.push_str(token);
+                    on_token(token);
+                }
+                if let Ok(event_usage) = serde_json::from_value::<TokenUsage>(event["usage"].clone()) {
+                    usage = Some(event_usage);
+                }
+            }
+        }
+
+        Ok((content, usage))
+    }
+
+    async fn get_structured_response(&self, messages: &[Message], schema: &ResponseSchema, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<Option<(String, Option<TokenUsage>)>, Box<dyn Error>> {
+        // async-openai doesn't know about functions or response formats yet.
+        let mut request = serde_json::to_value(self.create_request(messages, max_tokens, temperature))?;
+
+        match self.structured_output {
+            StructuredOutput::None => return Ok(None),
+            StructuredOutput::Functions => {
+                request["functions"] = json!([{
+                    "name": schema.name,
+                    "parameters": schema.schema
+                }]);
+                request["function_call"] = json!({ "name": schema.name });
+            },
+            StructuredOutput::JsonSchema => {
+                request["response_format"] = json!({
+                    "type": "json_schema",
+                    "json_schema": {
+                        "name": schema.name,
+                        "schema": schema.schema
+                    }
+                });
+            }
+        }
+
+        let response: Value = check_status(reqwest::Client::new()
+            .post(format!("{}/chat/completions", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()
+            .await?).await?
+            .json()
             .await?;
 
-        Ok(response.choices[0].message.content.clone())
+        let message = &response["choices"][0]["message"];
+        let content = match self.structured_output {
+            StructuredOutput::Functions => message["function_call"]["arguments"].as_str()
+                .ok_or(NoStructuredResponseError("function call".to_string()))?,
+            _ => message["content"].as_str()
+                .ok_or(NoStructuredResponseError("content".to_string()))?
+        };
+
+        let usage = serde_json::from_value::<TokenUsage>(response["usage"].clone()).ok();
+
+        Ok(Some((content.to_string(), usage)))
     }
 
     fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
@@ -86,6 +227,7 @@ pub struct ChatGPTConfig {
     pub model: Option<String>,
     #[serde(rename = "api base")] pub api_base: Option<String>,
     #[serde(rename = "embedding model")] pub embedding_model: Option<String>,
+    #[serde(rename = "structured output", default)] pub structured_output: StructuredOutput
 }
 
 impl Default for ChatGPTConfig {
@@ -94,7 +236,8 @@ impl Default for ChatGPTConfig {
             api_key: "Invalid API Key".to_string(),
             model: None,
             api_base: None,
-            embedding_model: None
+            embedding_model: None,
+            structured_output: StructuredOutput::None
         }
     }
 }
@@ -114,10 +257,15 @@ impl LLMProvider for ChatGPTProvider {
     fn create(&self, value: Value) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
         let config: ChatGPTConfig = serde_json::from_value(value)?;
 
+        let api_base = config.api_base.unwrap_or("https://api.openai.com/v1".to_owned());
+
         Ok(Box::new(ChatGPT {
             model: config.model.unwrap_or("gpt-3.5-turbo".to_string()),
             embedding_model: config.embedding_model.unwrap_or("text-embedding-ada-002".to_string()),
-            client: Client::new().with_api_base(config.api_base.unwrap_or("https://api.openai.com/v1".to_owned())).with_api_key(config.api_key.clone())
+            client: Client::new().with_api_base(api_base.clone()).with_api_key(config.api_key.clone()),
+            api_key: config.api_key,
+            api_base,
+            structured_output: config.structured_output
         }))
     }
 }
@@ -0,0 +1,194 @@
+use std::{error::Error, fmt::Display, collections::HashMap};
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+
+use crate::{LLMProvider, Message, LLMModel, TokenUsage, check_status, NoEmbeddingsError, estimate_token_count, estimate_tokens_from_text};
+
+#[derive(Debug, Clone)]
+pub struct CompatibleAPIError(pub String);
+
+impl Display for CompatibleAPIError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the OpenAI-compatible API failed: {}", self.0)
+    }
+}
+
+impl Error for CompatibleAPIError {}
+
+pub fn get_api_error(response: &Value) -> Option<CompatibleAPIError> {
+    let error = response.get("error")?;
+    let message = error["message"].as_str()
+        .or(error.as_str())
+        .unwrap_or("unknown error");
+
+    Some(CompatibleAPIError(message.to_string()))
+}
+
+/// Any server with OpenAI's chat completions API, like vLLM, LocalAI, LM Studio or OpenRouter.
+pub struct CompatibleModel {
+    pub model: String,
+    pub api_base: String,
+    pub embedding_model: Option<String>,
+    pub context_tokens: usize,
+    /// Has the authentication and any extra headers already set.
+    pub client: reqwest::Client
+}
+
+#[async_trait]
+impl LLMModel for CompatibleModel {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let messages = messages.iter()
+            .map(|message| json!({
+                "role": match message {
+                    Message::System(_) => "system",
+                    Message::User(_) => "user",
+                    Message::Assistant(_) => "assistant"
+                },
+                "content": message.content()
+            }))
+            .collect::<Vec<_>>();
+
+        let mut request = json!({
+            "model": self.model,
+            "messages": messages
+        });
+        if let Some(max_tokens) = max_tokens {
+            request["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(temperature) = temperature {
+            request["temperature"] = json!(temperature);
+        }
+
+        let response: Value = check_status(self.client
+            .post(format!("{}/chat/completions", self.api_base))
+            .json(&request)
+            .send()
+            .await?).await?
+            .json()
+            .await?;
+
+        if let Some(error) = get_api_error(&response) {
+            return Err(Box::new(error));
+        }
+
+        let content = response["choices"][0]["message"]["content"].as_str()
+            .ok_or(CompatibleAPIError("the response had no content".to_string()))?;
+        let usage = serde_json::from_value::<TokenUsage>(response["usage"].clone()).ok();
+
+        Ok((content.to_string(), usage))
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let embedding_model = match &self.embedding_model {
+            Some(embedding_model) => embedding_model,
+            None => return Err(Box::new(NoEmbeddingsError("An OpenAI-compatible model without an `embedding model`".to_string())))
+        };
+
+        let response: Value = check_status(self.client
+            .post(format!("{}/embeddings", self.api_base))
+            .json(&json!({
+                "model": embedding_model,
+                "input": text
+            }))
+            .send()
+            .await?).await?
+            .json()
+            .await?;
+
+        if let Some(error) = get_api_error(&response) {
+            return Err(Box::new(error));
+        }
+
+        Ok(serde_json::from_value(response["data"][0]["embedding"].clone())?)
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(estimate_token_count(messages))
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.context_tokens
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.context_tokens.saturating_sub(self.get_token_count(messages)?))
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        estimate_tokens_from_text(text)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompatibleConfig {
+    #[serde(rename = "api base")] pub api_base: String,
+    pub model: String,
+    #[serde(rename = "api key")] pub api_key: Option<String>,
+    /// The header the API key goes in. `Authorization` sends it as a bearer token,
+    /// any other header gets the key as it is.
+    #[serde(rename = "auth header")] pub auth_header: Option<String>,
+    #[serde(default)] pub headers: HashMap<String, String>,
+    /// Without one, embedding fails with `NoEmbeddingsError`, so agents need an `embedding` block to keep memories.
+    #[serde(rename = "embedding model")] pub embedding_model: Option<String>,
+    #[serde(rename = "context tokens")] pub context_tokens: Option<usize>
+}
+
+pub fn create_headers(config: &CompatibleConfig) -> Result<HeaderMap, Box<dyn Error>> {
+    let mut headers = HeaderMap::new();
+
+    if let Some(api_key) = &config.api_key {
+        let auth_header = config.auth_header.clone().unwrap_or("Authorization".to_string());
+        let value = if auth_header.eq_ignore_ascii_case("authorization") {
+            format!("Bearer {api_key}")
+        } else {
+            api_key.clone()
+        };
+
+        headers.insert(HeaderName::from_bytes(auth_header.as_bytes())?, HeaderValue::from_str(&value)?);
+    }
+
+    for (name, value) in &config.headers {
+        headers.insert(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+    }
+
+    Ok(headers)
+}
+
+pub struct CompatibleProvider;
+
+#[async_trait]
+impl LLMProvider for CompatibleProvider {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn get_name(&self) -> &str {
+        "openai compatible"
+    }
+
+    fn create(&self, value: Value) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
+        let config: CompatibleConfig = serde_json::from_value(value)?;
+        let headers = create_headers(&config)?;
+
+        Ok(Box::new(CompatibleModel {
+            model: config.model,
+            api_base: config.api_base.trim_end_matches('/').to_string(),
+            embedding_model: config.embedding_model,
+            context_tokens: config.context_tokens.unwrap_or(4096),
+            client: reqwest::Client::builder()
+                .default_headers(headers)
+                .build()?
+        }))
+    }
+}
+
+pub fn create_model_compatible() -> Box<dyn LLMProvider> {
+    Box::new(CompatibleProvider)
+}
@@ -0,0 +1,162 @@
+use std::{error::Error, fmt::Display, sync::atomic::{AtomicUsize, Ordering}};
+
+use async_trait::async_trait;
+use colored::Colorize;
+
+use crate::{LLMModel, Message, ResponseSchema, TokenUsage, OnToken};
+
+#[derive(Debug, Clone)]
+pub struct NoFallbackModelError;
+
+impl Display for NoFallbackModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no model in the fallback chain can fit the prompt")
+    }
+}
+
+impl Error for NoFallbackModelError {}
+
+/// Tries each of an agent's models in order, moving on to the next one when a request fails
+/// or the prompt doesn't fit in its context.
+pub struct FallbackModel {
+    pub models: Vec<Box<dyn LLMModel>>,
+    /// The model requests start from, raised when responses from the ones before it can't be parsed.
+    pub start: AtomicUsize,
+    /// The model that gave the last response.
+    pub last: AtomicUsize
+}
+
+impl FallbackModel {
+    pub fn new(models: Vec<Box<dyn LLMModel>>) -> Self {
+        Self {
+            models,
+            start: AtomicUsize::new(0),
+            last: AtomicUsize::new(0)
+        }
+    }
+
+    fn get_current(&self) -> &dyn LLMModel {
+        let start = self.start.load(Ordering::SeqCst).min(self.models.len() - 1);
+        self.models[start].as_ref()
+    }
+
+    /// The models to try, in order, skipping the ones whose context the prompt doesn't fit in.
+    fn get_candidates(&self, messages: &[Message]) -> Vec<usize> {
+        (self.start.load(Ordering::SeqCst)..self.models.len())
+            .filter(|&ind| {
+                let model = &self.models[ind];
+                model.get_token_count(messages)
+                    .map(|count| count <= model.get_token_limit())
+                    .unwrap_or(true)
+            })
+            .collect()
+    }
+}
+
+fn log_failure(ind: usize, error: &dyn Error) {
+    eprintln!("{}", format!("Model {} failed, falling back to the next one: {error}", ind + 1).red());
+}
+
+#[async_trait]
+impl LLMModel for FallbackModel {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let candidates = self.get_candidates(messages);
+
+        for (pos, &ind) in candidates.iter().enumerate() {
+            match self.models[ind].get_response_with_usage(messages, max_tokens, temperature).await {
+                Ok(response) => {
+                    self.last.store(ind, Ordering::SeqCst);
+                    return Ok(response);
+                },
+                Err(error) if pos + 1 < candidates.len() => log_failure(ind, error.as_ref()),
+                Err(error) => return Err(error)
+            }
+        }
+
+        Err(Box::new(NoFallbackModelError))
+    }
+
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let candidates = self.get_candidates(messages);
+
+        for (pos, &ind) in candidates.iter().enumerate() {
+            let mut streamed = false;
+            let mut forward = |token: &str| {
+                streamed = true;
+                on_token(token);
+            };
+
+            match self.models[ind].get_response_streaming(messages, max_tokens, temperature, &mut forward).await {
+                Ok(response) => {
+                    self.last.store(ind, Ordering::SeqCst);
+                    return Ok(response);
+                },
+                // Another model would start the response over after part of it was streamed.
+                Err(error) if pos + 1 < candidates.len() && !streamed => log_failure(ind, error.as_ref()),
+                Err(error) => return Err(error)
+            }
+        }
+
+        Err(Box::new(NoFallbackModelError))
+    }
+
+    async fn get_structured_response(&self, messages: &[Message], schema: &ResponseSchema, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<Option<(String, Option<TokenUsage>)>, Box<dyn Error>> {
+        let candidates = self.get_candidates(messages);
+
+        for (pos, &ind) in candidates.iter().enumerate() {
+            match self.models[ind].get_structured_response(messages, schema, max_tokens, temperature).await {
+                Ok(response) => {
+                    self.last.store(ind, Ordering::SeqCst);
+                    return Ok(response);
+                },
+                Err(error) if pos + 1 < candidates.len() => log_failure(ind, error.as_ref()),
+                Err(error) => return Err(error)
+            }
+        }
+
+        Err(Box::new(NoFallbackModelError))
+    }
+
+    /// Always embeds with the first model, since embeddings from different models can't be compared.
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        self.models[0].get_base_embed(text).await
+    }
+
+    fn escalate(&self) -> bool {
+        let next = self.last.load(Ordering::SeqCst).max(self.start.load(Ordering::SeqCst)) + 1;
+        if next >= self.models.len() {
+            return false;
+        }
+
+        self.start.store(next, Ordering::SeqCst);
+        true
+    }
+
+    fn reset_escalation(&self) {
+        self.start.store(0, Ordering::SeqCst);
+    }
+
+    fn discard_last_response(&self) {
+        self.models[self.last.load(Ordering::SeqCst)].discard_last_response();
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.get_current().get_token_count(messages)
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.get_current().get_token_limit()
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.get_current().get_tokens_remaining(messages)
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.get_current().get_tokens_from_text(text)
+    }
+}
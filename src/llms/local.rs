@@ -1,7 +1,7 @@
 use std::{error::Error, fmt::Display, path::Path};
 
 use async_trait::async_trait;
-use llm::{Model, InferenceParameters, load_dynamic, ModelParameters, InferenceSessionConfig, InferenceRequest, OutputRequest};
+use llm::{Model, InferenceParameters, load_dynamic, ModelParameters, InferenceSessionConfig, InferenceRequest, OutputRequest, TokenId};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -18,15 +18,31 @@ impl Display for NoLocalModelError {
 
 impl Error for NoLocalModelError {}
 
-use crate::{LLMProvider, LLMModel, Message, ModelLoadError, format_prompt};
+use crate::{LLMProvider, LLMModel, Message, ModelLoadError, NoEmbeddingsError, TokenUsage, OnToken, format_prompt};
 
 pub struct LocalLLM {
     pub model: Box<dyn Model>
 }
 
+impl LocalLLM {
+    /// Tokenizes with the model's own vocabulary, starting with its beginning-of-text token.
+    fn tokenize(&self, text: &str) -> Result<Vec<TokenId>, Box<dyn Error>> {
+        let tokens = self.model.vocabulary().tokenize(text, true)?
+            .into_iter()
+            .map(|(_, token)| token)
+            .collect();
+
+        Ok(tokens)
+    }
+}
+
 #[async_trait]
 impl LLMModel for LocalLLM {
     async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_streaming(messages, max_tokens, temperature, &mut |_| {}).await?.0)
+    }
+
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
         let session_config = InferenceSessionConfig::default();
         let mut session = self.model.start_session(session_config);
     
@@ -34,6 +50,8 @@ impl LLMModel for LocalLLM {
 
         let mut text = String::new();
         let prompt = format_prompt(messages);
+        // The prompt is echoed back before the response, so only what comes after it is streamed.
+        let mut streamed = prompt.len();
 
         session.infer(
             self.model.as_ref(), &mut rng,
@@ -53,27 +71,44 @@ impl LLMModel for LocalLLM {
             |token| {
                 text.push_str(token);
 
+                if let Some(new_text) = text.get(streamed..).filter(|el| !el.is_empty()) {
+                    on_token(new_text);
+                    streamed = text.len();
+                }
+
                 Ok::<_, ModelLoadError>(())
             }
         )?;
     
-        Ok(text.strip_prefix(&prompt).unwrap_or(&text).to_string())
+        Ok((text.strip_prefix(&prompt).unwrap_or(&text).to_string(), None))
     }
 
+    /// `llm` 0.1.1 only hands back the token IDs of a batch as its "embeddings", which it checks
+    /// against the size of a hidden state and panics, and it keeps the model's tensors private, so
+    /// local models can't embed text until `llm` is upgraded. Agents use an `embedding` block instead.
     async fn get_base_embed(&self, _text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
-        Ok(vec![])
+        Err(Box::new(NoEmbeddingsError("A local `llama` model".to_string())))
     }
 
-    fn get_token_count(&self, _text: &[Message]) -> Result<usize, Box<dyn Error>> {
-        Ok(0)
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.tokenize(&format_prompt(messages))?.len())
     }
 
     fn get_token_limit(&self) -> usize {
-        2048
+        self.model.n_context_tokens()
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.model.n_context_tokens().saturating_sub(self.get_token_count(messages)?))
     }
 
     fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
-        return Ok(vec![])
+        let tokens = self.model.vocabulary().tokenize(text, false)?
+            .into_iter()
+            .map(|(token, _)| String::from_utf8_lossy(token).to_string())
+            .collect();
+
+        Ok(tokens)
     }
 }
 
@@ -0,0 +1,189 @@
+use std::{error::Error, sync::{Arc, Mutex}, collections::HashMap};
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+
+use crate::{LLMModel, Message, ResponseSchema, OnToken};
+
+/// Dollars per 1,000 tokens.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64
+}
+
+pub fn get_default_prices() -> HashMap<String, ModelPrice> {
+    let prices = [
+        ("gpt-4-32k", 0.06, 0.12),
+        ("gpt-4", 0.03, 0.06),
+        ("gpt-3.5-turbo-16k", 0.003, 0.004),
+        ("gpt-3.5-turbo", 0.0015, 0.002)
+    ];
+
+    prices.iter()
+        .map(|&(name, prompt, completion)| (name.to_string(), ModelPrice { prompt, completion }))
+        .collect()
+}
+
+/// Finds the price of the model with the longest matching name, so `gpt-4-32k` isn't priced as `gpt-4`.
+/// Models that aren't listed are treated as free, like local models.
+pub fn get_model_price(prices: &HashMap<String, ModelPrice>, model: &str) -> ModelPrice {
+    prices.iter()
+        .filter(|(name, _)| model.starts_with(name.as_str()))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, price)| *price)
+        .unwrap_or_default()
+}
+
+/// The tokens a provider reports for one response.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub cost: f64
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> usize {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, call: &CallUsage) {
+        self.prompt_tokens += call.prompt_tokens;
+        self.completion_tokens += call.completion_tokens;
+        self.cost += call.cost;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallUsage {
+    pub agent: String,
+    pub model: String,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub cost: f64,
+    /// Whether the tokens were counted locally, because the provider didn't report them.
+    pub estimated: bool
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub total: Usage,
+    pub agents: HashMap<String, Usage>
+}
+
+/// Everything the agents of one run have used.
+#[derive(Default, Debug, Clone)]
+pub struct RunUsage {
+    pub summary: UsageSummary,
+    /// Calls that haven't been sent out as updates yet.
+    pub pending: Vec<CallUsage>
+}
+
+impl RunUsage {
+    pub fn record(&mut self, call: CallUsage) {
+        self.summary.total.add(&call);
+        self.summary.agents.entry(call.agent.clone())
+            .or_default()
+            .add(&call);
+        self.pending.push(call);
+    }
+
+    pub fn take_pending(&mut self) -> Vec<CallUsage> {
+        self.pending.drain(..).collect()
+    }
+}
+
+/// Wraps an agent's model to record the tokens and cost of every response.
+pub struct MeteredModel {
+    pub model: Box<dyn LLMModel>,
+    pub agent: String,
+    pub model_name: String,
+    pub price: ModelPrice,
+    pub usage: Arc<Mutex<RunUsage>>
+}
+
+impl MeteredModel {
+    fn record(&self, messages: &[Message], response: &str, reported: Option<TokenUsage>) -> Result<TokenUsage, Box<dyn Error>> {
+        let token_usage = match reported {
+            Some(token_usage) => token_usage,
+            None => TokenUsage {
+                prompt_tokens: self.model.get_token_count(messages)?,
+                completion_tokens: self.model.get_token_count(&[ Message::Assistant(response.to_string()) ])?
+            }
+        };
+
+        let cost = (
+            token_usage.prompt_tokens as f64 * self.price.prompt +
+            token_usage.completion_tokens as f64 * self.price.completion
+        ) / 1000.;
+
+        self.usage.lock().unwrap().record(CallUsage {
+            agent: self.agent.clone(),
+            model: self.model_name.clone(),
+            prompt_tokens: token_usage.prompt_tokens,
+            completion_tokens: token_usage.completion_tokens,
+            cost,
+            estimated: reported.is_none()
+        });
+
+        Ok(token_usage)
+    }
+}
+
+#[async_trait]
+impl LLMModel for MeteredModel {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let (response, reported) = self.model.get_response_with_usage(messages, max_tokens, temperature).await?;
+        let token_usage = self.record(messages, &response, reported)?;
+
+        Ok((response, Some(token_usage)))
+    }
+
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let (response, reported) = self.model.get_response_streaming(messages, max_tokens, temperature, on_token).await?;
+        let token_usage = self.record(messages, &response, reported)?;
+
+        Ok((response, Some(token_usage)))
+    }
+
+    async fn get_structured_response(&self, messages: &[Message], schema: &ResponseSchema, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<Option<(String, Option<TokenUsage>)>, Box<dyn Error>> {
+        match self.model.get_structured_response(messages, schema, max_tokens, temperature).await? {
+            Some((response, reported)) => {
+                let token_usage = self.record(messages, &response, reported)?;
+                Ok(Some((response, Some(token_usage))))
+            },
+            None => Ok(None)
+        }
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        self.model.get_base_embed(text).await
+    }
+
+    fn get_token_count(&self, text: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_token_count(text)
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.model.get_token_limit()
+    }
+
+    fn get_tokens_remaining(&self, text: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_tokens_remaining(text)
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.model.get_tokens_from_text(text)
+    }
+}
@@ -0,0 +1,345 @@
+use std::{error::Error, fmt::Display, collections::{HashMap, VecDeque}, sync::{Mutex, Arc}, fs};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{LLMProvider, Message, LLMModel, TokenUsage, OnToken, create_llm_providers, hash_embedding, estimate_token_count, estimate_tokens_from_text};
+
+#[derive(Debug, Clone)]
+pub struct NoMockResponseError(pub String);
+
+impl Display for NoMockResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no mock response for: {}", self.0)
+    }
+}
+
+impl Error for NoMockResponseError {}
+
+#[derive(Debug, Clone)]
+pub struct InvalidMockConfigError(pub String);
+
+impl Display for InvalidMockConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid mock config: {}", self.0)
+    }
+}
+
+impl Error for InvalidMockConfigError {}
+
+fn summarize(messages: &[Message]) -> String {
+    let content = messages.last().map(|el| el.content()).unwrap_or("");
+    content.chars().take(200).collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MockResponse {
+    /// A regex matched against the last message. Responses with a pattern can be served any number
+    /// of times, while ones without are served once each, in order.
+    pub pattern: Option<String>,
+    pub response: String
+}
+
+/// Serves scripted responses, for running agents without a real model.
+pub struct MockLLM {
+    pub patterns: Vec<(Regex, String)>,
+    pub queue: Mutex<VecDeque<String>>,
+    pub dimensions: usize,
+    pub context_tokens: usize
+}
+
+#[async_trait]
+impl LLMModel for MockLLM {
+    async fn get_response(&self, messages: &[Message], _max_tokens: Option<u16>, _temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        let last = messages.last().map(|el| el.content()).unwrap_or("");
+
+        if let Some((_, response)) = self.patterns.iter().find(|(pattern, _)| pattern.is_match(last)) {
+            return Ok(response.clone());
+        }
+
+        self.queue.lock().unwrap()
+            .pop_front()
+            .ok_or(Box::new(NoMockResponseError(summarize(messages))) as Box<dyn Error>)
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        Ok(hash_embedding(text, self.dimensions))
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(estimate_token_count(messages))
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.context_tokens
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.context_tokens.saturating_sub(self.get_token_count(messages)?))
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        estimate_tokens_from_text(text)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedResponse {
+    pub messages: Vec<Message>,
+    pub response: String,
+    pub usage: Option<TokenUsage>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedEmbedding {
+    pub text: String,
+    pub embedding: Vec<f32>
+}
+
+/// The traffic of a recorded run.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Fixture {
+    pub responses: Vec<RecordedResponse>,
+    pub embeddings: Vec<RecordedEmbedding>
+}
+
+/// The fixtures being recorded, so agents recording to the same file don't overwrite each other.
+static RECORDINGS: Mutex<Vec<(String, Arc<Mutex<Fixture>>)>> = Mutex::new(vec![]);
+
+pub fn get_recording(path: &str) -> Arc<Mutex<Fixture>> {
+    let mut recordings = RECORDINGS.lock().unwrap();
+    if let Some((_, fixture)) = recordings.iter().find(|(el, _)| el == path) {
+        return fixture.clone();
+    }
+
+    let fixture = Arc::new(Mutex::new(Fixture::default()));
+    recordings.push((path.to_string(), fixture.clone()));
+    fixture
+}
+
+/// Passes calls on to a real model, saving them to a fixture file as it goes.
+pub struct RecordingLLM {
+    pub model: Box<dyn LLMModel>,
+    pub path: String,
+    pub fixture: Arc<Mutex<Fixture>>
+}
+
+impl RecordingLLM {
+    fn save(&self, fixture: &Fixture) -> Result<(), Box<dyn Error>> {
+        fs::write(&self.path, serde_json::to_string_pretty(fixture)?)?;
+        Ok(())
+    }
+
+    fn record(&self, messages: &[Message], response: &str, usage: Option<TokenUsage>) -> Result<(), Box<dyn Error>> {
+        let mut fixture = self.fixture.lock().unwrap();
+        fixture.responses.push(RecordedResponse {
+            messages: messages.to_vec(),
+            response: response.to_string(),
+            usage
+        });
+        self.save(&fixture)
+    }
+}
+
+#[async_trait]
+impl LLMModel for RecordingLLM {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let (response, usage) = self.model.get_response_with_usage(messages, max_tokens, temperature).await?;
+        self.record(messages, &response, usage)?;
+
+        Ok((response, usage))
+    }
+
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let (response, usage) = self.model.get_response_streaming(messages, max_tokens, temperature, on_token).await?;
+        self.record(messages, &response, usage)?;
+
+        Ok((response, usage))
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let embedding = self.model.get_base_embed(text).await?;
+
+        let mut fixture = self.fixture.lock().unwrap();
+        fixture.embeddings.push(RecordedEmbedding {
+            text: text.to_string(),
+            embedding: embedding.clone()
+        });
+        self.save(&fixture)?;
+
+        Ok(embedding)
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_token_count(messages)
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.model.get_token_limit()
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_tokens_remaining(messages)
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.model.get_tokens_from_text(text)
+    }
+}
+
+/// Serves the traffic of a recorded run. Calls are matched by their exact input, and repeated
+/// inputs get their recorded responses in order.
+pub struct ReplayLLM {
+    pub responses: Mutex<HashMap<String, VecDeque<RecordedResponse>>>,
+    pub embeddings: Mutex<HashMap<String, VecDeque<Vec<f32>>>>,
+    pub context_tokens: usize
+}
+
+impl ReplayLLM {
+    pub fn new(fixture: Fixture, context_tokens: usize) -> Result<Self, Box<dyn Error>> {
+        let mut responses: HashMap<String, VecDeque<RecordedResponse>> = HashMap::new();
+        for response in fixture.responses {
+            responses.entry(serde_json::to_string(&response.messages)?)
+                .or_default()
+                .push_back(response);
+        }
+
+        let mut embeddings: HashMap<String, VecDeque<Vec<f32>>> = HashMap::new();
+        for embedding in fixture.embeddings {
+            embeddings.entry(embedding.text)
+                .or_default()
+                .push_back(embedding.embedding);
+        }
+
+        Ok(Self {
+            responses: Mutex::new(responses),
+            embeddings: Mutex::new(embeddings),
+            context_tokens
+        })
+    }
+}
+
+#[async_trait]
+impl LLMModel for ReplayLLM {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], _max_tokens: Option<u16>, _temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let key = serde_json::to_string(messages)?;
+
+        let recorded = self.responses.lock().unwrap()
+            .get_mut(&key)
+            .and_then(|el| el.pop_front())
+            .ok_or(NoMockResponseError(summarize(messages)))?;
+
+        Ok((recorded.response, recorded.usage))
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let embedding = self.embeddings.lock().unwrap()
+            .get_mut(text)
+            .and_then(|el| el.pop_front())
+            .ok_or(NoMockResponseError(text.chars().take(200).collect()))?;
+
+        Ok(embedding)
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(estimate_token_count(messages))
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.context_tokens
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.context_tokens.saturating_sub(self.get_token_count(messages)?))
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        estimate_tokens_from_text(text)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MockConfig {
+    #[serde(default)] pub responses: Vec<MockResponse>,
+    /// Records the `llm` model's traffic to this fixture file.
+    pub record: Option<String>,
+    /// Serves the traffic recorded in this fixture file.
+    pub replay: Option<String>,
+    /// The real model to record, configured like an agent's `llm`.
+    pub llm: Option<HashMap<String, Value>>,
+    /// The size of the hashed embeddings of scripted responses.
+    pub dimensions: Option<usize>,
+    #[serde(rename = "context tokens")] pub context_tokens: Option<usize>
+}
+
+pub struct MockProvider;
+
+#[async_trait]
+impl LLMProvider for MockProvider {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn get_name(&self) -> &str {
+        "mock"
+    }
+
+    fn create(&self, value: Value) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
+        let config: MockConfig = serde_json::from_value(value)?;
+        let context_tokens = config.context_tokens.unwrap_or(4096);
+
+        if let Some(path) = config.record {
+            let llm = config.llm
+                .ok_or(InvalidMockConfigError("recording needs an `llm` to record".to_string()))?;
+            let (name, model_config) = llm.into_iter().next()
+                .ok_or(InvalidMockConfigError("recording needs an `llm` to record".to_string()))?;
+
+            let providers = create_llm_providers();
+            let provider = providers.iter()
+                .filter(|el| el.is_enabled())
+                .find(|el| el.get_name().eq_ignore_ascii_case(&name))
+                .ok_or(InvalidMockConfigError(format!("no model provider named '{name}'")))?;
+
+            return Ok(Box::new(RecordingLLM {
+                model: provider.create(model_config)?,
+                fixture: get_recording(&path),
+                path
+            }));
+        }
+
+        if let Some(path) = config.replay {
+            let fixture: Fixture = serde_json::from_str(&fs::read_to_string(path)?)?;
+            return Ok(Box::new(ReplayLLM::new(fixture, context_tokens)?));
+        }
+
+        let mut patterns = vec![];
+        let mut queue = VecDeque::new();
+        for MockResponse { pattern, response } in config.responses {
+            match pattern {
+                Some(pattern) => patterns.push((Regex::new(&pattern)?, response)),
+                None => queue.push_back(response)
+            }
+        }
+
+        Ok(Box::new(MockLLM {
+            patterns,
+            queue: Mutex::new(queue),
+            dimensions: config.dimensions.unwrap_or(64),
+            context_tokens
+        }))
+    }
+}
+
+pub fn create_model_mock() -> Box<dyn LLMProvider> {
+    Box::new(MockProvider)
+}
@@ -1,15 +1,34 @@
 mod chatgpt;
 mod local;
+mod metered;
+mod anthropic;
+mod compatible;
+mod ollama;
+mod mock;
+mod retry;
+mod fallback;
+mod cache;
 
 pub use chatgpt::*;
 pub use local::*;
-use serde::Serialize;
-use tokio::runtime::Runtime;
+pub use metered::*;
+pub use anthropic::*;
+pub use compatible::*;
+pub use ollama::*;
+pub use mock::*;
+pub use retry::*;
+pub use fallback::*;
+pub use cache::*;
+use serde::{Serialize, Deserialize};
+use tokio::{runtime::Runtime, sync::mpsc::unbounded_channel};
 
 use std::{error::Error, fmt::Display};
 
 use async_trait::async_trait;
 use serde_json::Value;
+use tiktoken_rs::cl100k_base_singleton;
+
+use crate::EmbeddingModel;
 
 #[derive(Debug, Clone)]
 pub struct ModelLoadError(pub String);
@@ -22,11 +41,22 @@ impl Display for ModelLoadError {
 
 impl Error for ModelLoadError {}
 
-#[derive(Clone, Debug)]
+#[derive(Debug, Clone)]
+pub struct NoEmbeddingsError(pub String);
+
+impl Display for NoEmbeddingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} can't embed memories. Add an `embedding` block to the agent to embed them with another provider.", self.0)
+    }
+}
+
+impl Error for NoEmbeddingsError {}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
-    User(String),
-    Assistant(String),
-    System(String)
+    #[serde(rename = "user")] User(String),
+    #[serde(rename = "assistant")] Assistant(String),
+    #[serde(rename = "system")] System(String)
 }
 
 impl Display for Message {
@@ -81,11 +111,55 @@ impl Message {
     }
 }
 
+/// A JSON schema that a structured response has to follow.
+#[derive(Debug, Clone)]
+pub struct ResponseSchema {
+    pub name: String,
+    pub schema: Value
+}
+
+/// Receives the pieces of a streamed response.
+pub type OnToken<'a> = dyn FnMut(&str) + Send + 'a;
+
+/// Receives the pieces of a streamed response on the calling thread, and can stop the run by failing.
+pub type ListenToToken<'a> = dyn FnMut(&str) -> Result<(), Box<dyn Error>> + 'a;
+
 #[async_trait]
 pub trait LLMModel : Send + Sync {
     async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>>;
+
+    /// Also gives the tokens the provider reports using, if it reports them.
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        Ok((self.get_response(messages, max_tokens, temperature).await?, None))
+    }
+
+    /// Gets a JSON response that follows `schema`, using the provider's structured output.
+    /// Gives `None` if the provider doesn't support it, so the caller can parse text instead.
+    async fn get_structured_response(&self, _messages: &[Message], _schema: &ResponseSchema, _max_tokens: Option<u16>, _temperature: Option<f32>) -> Result<Option<(String, Option<TokenUsage>)>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// Calls `on_token` with each piece of the response as it's generated. Providers that can't
+    /// stream give the whole response as one piece.
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let (response, usage) = self.get_response_with_usage(messages, max_tokens, temperature).await?;
+        on_token(&response);
+        Ok((response, usage))
+    }
+
     async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>>;
 
+    /// Moves on to a stronger model after responses couldn't be parsed. Gives `false` if there isn't one.
+    fn escalate(&self) -> bool {
+        false
+    }
+
+    /// Goes back to the first model once a response is parsed, or parsing is given up on.
+    fn reset_escalation(&self) {}
+
+    /// Drops the last response from the cache, if there is one, because it couldn't be used.
+    fn discard_last_response(&self) {}
+
     fn get_token_count(&self, text: &[Message]) -> Result<usize, Box<dyn Error>>;
     fn get_token_limit(&self) -> usize;
 
@@ -99,6 +173,38 @@ pub trait LLMModel : Send + Sync {
             self.get_response(messages, max_tokens, temperature).await
         })
     }
+    /// Passes tokens to `on_token` on this thread while they're being generated, so it doesn't need to be `Send`.
+    fn get_response_streaming_sync(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut ListenToToken<'_>) -> Result<String, Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(async {
+            let (sender, mut receiver) = unbounded_channel::<String>();
+
+            let response = async move {
+                let mut send = move |token: &str| {
+                    // The receiver is only gone if `on_token` failed, which is reported below.
+                    let _ = sender.send(token.to_string());
+                };
+                self.get_response_streaming(messages, max_tokens, temperature, &mut send).await
+            };
+
+            let listen = async {
+                while let Some(token) = receiver.recv().await {
+                    on_token(&token)?;
+                }
+                Ok::<(), Box<dyn Error>>(())
+            };
+
+            let (response, listened) = tokio::join!(response, listen);
+            listened?;
+            Ok(response?.0)
+        })
+    }
+    fn get_structured_response_sync(&self, messages: &[Message], schema: &ResponseSchema, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<Option<String>, Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(async {
+            Ok(self.get_structured_response(messages, schema, max_tokens, temperature).await?.map(|(response, _)| response))
+        })
+    }
     fn get_base_embed_sync(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
         let rt = Runtime::new()?;
         rt.block_on(async {
@@ -120,7 +226,9 @@ pub struct LLM {
     pub prompt: Vec<Message>,
     pub end_prompt: Vec<Message>,
     pub message_history: Vec<Message>,
-    pub model: Box<dyn LLMModel>
+    pub model: Box<dyn LLMModel>,
+    /// Embeds memories instead of the model, if the agent has an `embedding` configured.
+    pub embedding: Option<Box<dyn EmbeddingModel>>
 }
 
 impl LLM {
@@ -135,7 +243,15 @@ impl LLM {
             prompt: vec![],
             end_prompt: vec![],
             message_history: vec![],
-            model
+            model,
+            embedding: None
+        }
+    }
+
+    pub async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        match &self.embedding {
+            Some(embedding) => embedding.get_embedding(text).await,
+            None => self.model.get_base_embed(text).await
         }
     }
 
@@ -199,4 +315,27 @@ pub fn format_prompt(messages: &[Message]) -> String {
     out.push_str("ASSISTANT: ");
 
     out
-}
\ No newline at end of file
+}
+
+/// Counts tokens with OpenAI's `cl100k_base` encoding, for models that don't have a tokenizer
+/// available here. Other tokenizers give different counts, so this is only an estimate.
+pub fn estimate_token_count(messages: &[Message]) -> usize {
+    let bpe = cl100k_base_singleton();
+    let bpe = bpe.lock();
+
+    // Every message has a few tokens of overhead for its role, and the reply is primed with a few more.
+    messages.iter()
+        .map(|message| 4 + bpe.encode_with_special_tokens(message.content()).len())
+        .sum::<usize>() + 3
+}
+
+pub fn estimate_tokens_from_text(text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let bpe = cl100k_base_singleton();
+    let bpe = bpe.lock();
+
+    let tokens = bpe.encode_ordinary(text).iter()
+        .flat_map(|&token| bpe.decode(vec![ token ]))
+        .collect::<Vec<_>>();
+
+    Ok(tokens)
+}
@@ -0,0 +1,197 @@
+use std::{error::Error, fmt::Display};
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+
+use crate::{LLMProvider, Message, LLMModel, TokenUsage, check_status, format_prompt, estimate_token_count, estimate_tokens_from_text};
+
+#[derive(Debug, Clone)]
+pub struct LocalServerError(pub String);
+
+impl Display for LocalServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the local model server failed: {}", self.0)
+    }
+}
+
+impl Error for LocalServerError {}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LocalServerAPI {
+    /// Ollama's `/api/chat` and `/api/embeddings`.
+    #[default]
+    #[serde(rename = "ollama")] Ollama,
+    /// llama.cpp's `server` example, with `/completion` and `/embedding`.
+    #[serde(rename = "llama.cpp")] LlamaCpp
+}
+
+/// A model served locally over HTTP, by Ollama or a llama.cpp server.
+pub struct LocalServerModel {
+    pub api: LocalServerAPI,
+    pub url: String,
+    pub model: String,
+    pub context_tokens: usize,
+    pub client: reqwest::Client
+}
+
+impl LocalServerModel {
+    async fn post(&self, path: &str, request: Value) -> Result<Value, Box<dyn Error>> {
+        let response: Value = check_status(self.client
+            .post(format!("{}{}", self.url, path))
+            .json(&request)
+            .send()
+            .await?).await?
+            .json()
+            .await?;
+
+        if let Some(error) = response.get("error") {
+            let message = error.as_str()
+                .or(error["message"].as_str())
+                .unwrap_or("unknown error");
+            return Err(Box::new(LocalServerError(message.to_string())));
+        }
+
+        Ok(response)
+    }
+}
+
+fn get_usage(response: &Value, prompt_field: &str, completion_field: &str) -> Option<TokenUsage> {
+    Some(TokenUsage {
+        prompt_tokens: response[prompt_field].as_u64()? as usize,
+        completion_tokens: response[completion_field].as_u64()? as usize
+    })
+}
+
+#[async_trait]
+impl LLMModel for LocalServerModel {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        match self.api {
+            LocalServerAPI::Ollama => {
+                let messages = messages.iter()
+                    .map(|message| json!({
+                        "role": match message {
+                            Message::System(_) => "system",
+                            Message::User(_) => "user",
+                            Message::Assistant(_) => "assistant"
+                        },
+                        "content": message.content()
+                    }))
+                    .collect::<Vec<_>>();
+
+                let mut options = json!({ "num_ctx": self.context_tokens });
+                if let Some(max_tokens) = max_tokens {
+                    options["num_predict"] = json!(max_tokens);
+                }
+                if let Some(temperature) = temperature {
+                    options["temperature"] = json!(temperature);
+                }
+
+                let response = self.post("/api/chat", json!({
+                    "model": self.model,
+                    "messages": messages,
+                    "stream": false,
+                    "options": options
+                })).await?;
+
+                let content = response["message"]["content"].as_str()
+                    .ok_or(LocalServerError("the response had no content".to_string()))?;
+
+                Ok((content.to_string(), get_usage(&response, "prompt_eval_count", "eval_count")))
+            },
+            LocalServerAPI::LlamaCpp => {
+                let mut request = json!({ "prompt": format_prompt(messages) });
+                if let Some(max_tokens) = max_tokens {
+                    request["n_predict"] = json!(max_tokens);
+                }
+                if let Some(temperature) = temperature {
+                    request["temperature"] = json!(temperature);
+                }
+
+                let response = self.post("/completion", request).await?;
+
+                let content = response["content"].as_str()
+                    .ok_or(LocalServerError("the response had no content".to_string()))?;
+
+                Ok((content.to_string(), get_usage(&response, "tokens_evaluated", "tokens_predicted")))
+            }
+        }
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let response = match self.api {
+            LocalServerAPI::Ollama => self.post("/api/embeddings", json!({
+                "model": self.model,
+                "prompt": text
+            })).await?,
+            LocalServerAPI::LlamaCpp => self.post("/embedding", json!({
+                "content": text
+            })).await?
+        };
+
+        Ok(serde_json::from_value(response["embedding"].clone())?)
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(estimate_token_count(messages))
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.context_tokens
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.context_tokens.saturating_sub(self.get_token_count(messages)?))
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        estimate_tokens_from_text(text)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LocalServerConfig {
+    #[serde(default)] pub api: LocalServerAPI,
+    pub url: Option<String>,
+    /// Only used by Ollama, since a llama.cpp server runs a single model.
+    pub model: Option<String>,
+    #[serde(rename = "context tokens")] pub context_tokens: Option<usize>
+}
+
+pub struct LocalServerProvider;
+
+#[async_trait]
+impl LLMProvider for LocalServerProvider {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn get_name(&self) -> &str {
+        "ollama"
+    }
+
+    fn create(&self, value: Value) -> Result<Box<dyn LLMModel>, Box<dyn Error>> {
+        let config: LocalServerConfig = serde_json::from_value(value)?;
+
+        let default_url = match config.api {
+            LocalServerAPI::Ollama => "http://localhost:11434",
+            LocalServerAPI::LlamaCpp => "http://localhost:8080"
+        };
+
+        Ok(Box::new(LocalServerModel {
+            api: config.api,
+            url: config.url.unwrap_or(default_url.to_string()).trim_end_matches('/').to_string(),
+            model: config.model.unwrap_or("llama2".to_string()),
+            context_tokens: config.context_tokens.unwrap_or(4096),
+            client: reqwest::Client::new()
+        }))
+    }
+}
+
+pub fn create_model_ollama() -> Box<dyn LLMProvider> {
+    Box::new(LocalServerProvider)
+}
@@ -0,0 +1,547 @@
+use std::{error::Error, fmt::Display, time::{Duration, Instant}, sync::{Mutex, Arc}, collections::VecDeque, future::Future, pin::Pin};
+
+use async_openai::error::OpenAIError;
+use async_trait::async_trait;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use tokio::{sync::{Semaphore, SemaphorePermit}, time::{sleep, timeout}};
+
+use crate::{LLMModel, Message, ResponseSchema, TokenUsage, OnToken};
+
+/// A provider answered with an error status.
+#[derive(Debug, Clone)]
+pub struct HttpStatusError {
+    pub status: u16,
+    /// How long the provider asked us to wait before trying again.
+    pub retry_after: Option<Duration>,
+    pub message: String
+}
+
+impl Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the request failed with status {}: {}", self.status, self.message)
+    }
+}
+
+impl Error for HttpStatusError {}
+
+#[derive(Debug, Clone)]
+pub struct RequestTimeoutError(pub Duration);
+
+impl Display for RequestTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the request timed out after {:.0} seconds", self.0.as_secs_f64())
+    }
+}
+
+impl Error for RequestTimeoutError {}
+
+fn get_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let get_seconds = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+
+    get_seconds("retry-after-ms").map(|ms| ms / 1000.)
+        .or_else(|| get_seconds("retry-after"))
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.)
+        .map(Duration::from_secs_f64)
+}
+
+/// Turns an error status into an `HttpStatusError`, so it can be retried.
+pub async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, HttpStatusError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = get_retry_after(response.headers());
+    let message = response.text().await.unwrap_or_default();
+
+    Err(HttpStatusError {
+        status: status.as_u16(),
+        retry_after,
+        message: message.chars().take(500).collect()
+    })
+}
+
+fn is_retryable_status(status: u16) -> bool {
+    status == 408 || status == 429 || status >= 500
+}
+
+fn is_retryable_reqwest(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() ||
+        error.status().map(|status| is_retryable_status(status.as_u16())).unwrap_or(false)
+}
+
+/// Gives `Some` with the delay the provider asked for, if any, when an error is worth retrying.
+pub fn get_retry_delay(error: &(dyn Error + 'static)) -> Option<Option<Duration>> {
+    if let Some(error) = error.downcast_ref::<HttpStatusError>() {
+        return is_retryable_status(error.status).then_some(error.retry_after);
+    }
+
+    if error.is::<RequestTimeoutError>() {
+        return Some(None);
+    }
+
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return is_retryable_reqwest(error).then_some(None);
+    }
+
+    match error.downcast_ref::<OpenAIError>() {
+        Some(OpenAIError::Reqwest(error)) => is_retryable_reqwest(error).then_some(None),
+        Some(OpenAIError::ApiError(error)) => (error.r#type == "server_error").then_some(None),
+        _ => None
+    }
+}
+
+/// How failed requests are retried. Delays are in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    #[serde(rename = "max retries")] pub max_retries: u32,
+    #[serde(rename = "initial delay")] pub initial_delay: f64,
+    #[serde(rename = "max delay")] pub max_delay: f64,
+    /// How long one attempt can take before it's cancelled and retried. Attempts never time out without one.
+    pub timeout: Option<f64>
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: 1.,
+            max_delay: 60.,
+            timeout: Some(300.)
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Doubles the delay with every retry, picking a random point in its upper half so agents
+    /// that were limited together don't all retry together.
+    pub fn get_backoff(&self, retry: u32) -> Duration {
+        let delay = (self.initial_delay * 2f64.powi(retry as i32)).min(self.max_delay).max(0.);
+        let jittered = rand::thread_rng().gen_range(delay / 2. ..=delay);
+
+        Duration::from_secs_f64(jittered)
+    }
+
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+            .filter(|seconds| seconds.is_finite() && *seconds > 0.)
+            .map(Duration::from_secs_f64)
+    }
+}
+
+/// The limits on requests to one provider, shared by every agent using it.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// How many requests can run at once.
+    pub concurrency: Option<usize>,
+    #[serde(rename = "requests per minute")] pub requests_per_minute: Option<usize>
+}
+
+pub struct RateLimiter {
+    pub semaphore: Option<Semaphore>,
+    pub requests_per_minute: Option<usize>,
+    /// When each request in the last minute started.
+    pub requests: Mutex<VecDeque<Instant>>
+}
+
+impl RateLimiter {
+    pub fn new(limit: &RateLimit) -> Self {
+        Self {
+            semaphore: limit.concurrency.map(|concurrency| Semaphore::new(concurrency.max(1))),
+            requests_per_minute: limit.requests_per_minute,
+            requests: Mutex::new(VecDeque::new())
+        }
+    }
+
+    /// Waits until a request can be sent. The request counts against the concurrency limit until
+    /// the permit is dropped.
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let permit = match &self.semaphore {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None
+        };
+
+        if let Some(requests_per_minute) = self.requests_per_minute {
+            let minute = Duration::from_secs(60);
+
+            loop {
+                let wait = {
+                    let mut requests = self.requests.lock().unwrap();
+                    let now = Instant::now();
+                    while requests.front().map(|el| now.duration_since(*el) >= minute).unwrap_or(false) {
+                        requests.pop_front();
+                    }
+
+                    if requests.len() < requests_per_minute.max(1) {
+                        requests.push_back(now);
+                        None
+                    } else {
+                        requests.front().map(|el| minute.saturating_sub(now.duration_since(*el)))
+                    }
+                };
+
+                match wait {
+                    Some(wait) => sleep(wait).await,
+                    None => break
+                }
+            }
+        }
+
+        permit
+    }
+}
+
+/// Every config loaded in this process shares one limiter for each provider, since the limits
+/// are on the provider's side. A config with different limits for a provider replaces its limiter.
+static RATE_LIMITERS: Mutex<Vec<(String, RateLimit, Arc<RateLimiter>)>> = Mutex::new(vec![]);
+
+pub fn get_rate_limiter(provider: &str, limit: &RateLimit) -> Arc<RateLimiter> {
+    let provider = provider.to_ascii_lowercase();
+    let mut limiters = RATE_LIMITERS.lock().unwrap();
+
+    if let Some((_, _, limiter)) = limiters.iter().find(|(name, el, _)| *name == provider && el == limit) {
+        return limiter.clone();
+    }
+
+    let limiter = Arc::new(RateLimiter::new(limit));
+    limiters.retain(|(name, _, _)| *name != provider);
+    limiters.push((provider, limit.clone(), limiter.clone()));
+    limiter
+}
+
+type ModelFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error>>> + Send + 'a>>;
+
+/// Wraps a model to retry failed requests with backoff, and to keep to its provider's rate limits.
+pub struct RetryModel {
+    pub model: Box<dyn LLMModel>,
+    pub policy: RetryPolicy,
+    pub limiter: Option<Arc<RateLimiter>>
+}
+
+impl RetryModel {
+    async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.limiter {
+            Some(limiter) => limiter.acquire().await,
+            None => None
+        }
+    }
+
+    async fn attempt<'a, T>(&self, call: ModelFuture<'a, T>) -> Result<T, Box<dyn Error>> {
+        let _permit = self.acquire().await;
+
+        match self.policy.get_timeout() {
+            Some(duration) => match timeout(duration, call).await {
+                Ok(result) => result,
+                Err(_) => Err(Box::new(RequestTimeoutError(duration)))
+            },
+            None => call.await
+        }
+    }
+
+    /// Gives the delay before the next try, or `None` if the error should be given up on.
+    fn get_delay(&self, error: &(dyn Error + 'static), retry: u32) -> Option<Duration> {
+        if retry >= self.policy.max_retries {
+            return None;
+        }
+
+        // A provider's `Retry-After` can't stall the agent for longer than the policy allows.
+        let max_delay = Duration::from_secs_f64(self.policy.max_delay.max(0.));
+        let retry_after = get_retry_delay(error)?;
+        Some(retry_after.map(|el| el.min(max_delay)).unwrap_or_else(|| self.policy.get_backoff(retry)))
+    }
+
+    async fn retry<'a, T: Send>(&'a self, mut call: impl FnMut() -> ModelFuture<'a, T> + Send) -> Result<T, Box<dyn Error>> {
+        let mut retry = 0;
+
+        loop {
+            let delay = match self.attempt(call()).await {
+                Ok(value) => return Ok(value),
+                Err(error) => match self.get_delay(error.as_ref(), retry) {
+                    Some(delay) => delay,
+                    None => return Err(error)
+                }
+            };
+
+            sleep(delay).await;
+            retry += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl LLMModel for RetryModel {
+    async fn get_response(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_response_with_usage(messages, max_tokens, temperature).await?.0)
+    }
+
+    async fn get_response_with_usage(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        self.retry(|| self.model.get_response_with_usage(messages, max_tokens, temperature)).await
+    }
+
+    async fn get_response_streaming(&self, messages: &[Message], max_tokens: Option<u16>, temperature: Option<f32>, on_token: &mut OnToken<'_>) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let mut retry = 0;
+
+        loop {
+            let mut streamed = false;
+            let delay = {
+                let mut forward = |token: &str| {
+                    streamed = true;
+                    on_token(token);
+                };
+
+                match self.attempt(self.model.get_response_streaming(messages, max_tokens, temperature, &mut forward)).await {
+                    Ok(value) => return Ok(value),
+                    // Trying again after some of the response was streamed would repeat it.
+                    Err(error) => match self.get_delay(error.as_ref(), retry).filter(|_| !streamed) {
+                        Some(delay) => delay,
+                        None => return Err(error)
+                    }
+                }
+            };
+
+            sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    async fn get_structured_response(&self, messages: &[Message], schema: &ResponseSchema, max_tokens: Option<u16>, temperature: Option<f32>) -> Result<Option<(String, Option<TokenUsage>)>, Box<dyn Error>> {
+        self.retry(|| self.model.get_structured_response(messages, schema, max_tokens, temperature)).await
+    }
+
+    async fn get_base_embed(&self, text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        self.retry(|| self.model.get_base_embed(text)).await
+    }
+
+    fn get_token_count(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_token_count(messages)
+    }
+
+    fn get_token_limit(&self) -> usize {
+        self.model.get_token_limit()
+    }
+
+    fn get_tokens_remaining(&self, messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+        self.model.get_tokens_remaining(messages)
+    }
+
+    fn get_tokens_from_text(&self, text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.model.get_tokens_from_text(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener, sync::atomic::{AtomicUsize, Ordering}, thread};
+
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    use crate::create_model_compatible;
+
+    use super::*;
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_delay: 0.001,
+            max_delay: 0.001,
+            timeout: Some(5.)
+        }
+    }
+
+    /// Fails with `status` a number of times, then answers.
+    struct FailingModel {
+        status: u16,
+        failures: usize,
+        attempts: Arc<AtomicUsize>
+    }
+
+    #[async_trait]
+    impl LLMModel for FailingModel {
+        async fn get_response(&self, _messages: &[Message], _max_tokens: Option<u16>, _temperature: Option<f32>) -> Result<String, Box<dyn Error>> {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst);
+            if attempt < self.failures {
+                return Err(Box::new(HttpStatusError {
+                    status: self.status,
+                    retry_after: Some(Duration::ZERO),
+                    message: "failed".to_string()
+                }));
+            }
+
+            Ok("done".to_string())
+        }
+
+        async fn get_base_embed(&self, _text: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+
+        fn get_token_count(&self, _messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+            Ok(0)
+        }
+
+        fn get_token_limit(&self) -> usize {
+            4096
+        }
+
+        fn get_tokens_remaining(&self, _messages: &[Message]) -> Result<usize, Box<dyn Error>> {
+            Ok(4096)
+        }
+
+        fn get_tokens_from_text(&self, _text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+    }
+
+    /// Gives the model, and a count of the attempts made with it.
+    fn failing_model(status: u16, failures: usize, max_retries: u32) -> (RetryModel, Arc<AtomicUsize>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let model = RetryModel {
+            model: Box::new(FailingModel { status, failures, attempts: attempts.clone() }),
+            policy: policy(max_retries),
+            limiter: None
+        };
+
+        (model, attempts)
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(get_retry_after(&headers), None);
+
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        assert_eq!(get_retry_after(&headers), Some(Duration::from_secs(2)));
+
+        headers.insert("retry-after-ms", HeaderValue::from_static("1500"));
+        assert_eq!(get_retry_after(&headers), Some(Duration::from_millis(1500)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("-1"));
+        assert_eq!(get_retry_after(&headers), None);
+
+        // Dates aren't supported, so they fall back to the backoff.
+        headers.insert("retry-after", HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(get_retry_after(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_requests() {
+        let (model, attempts) = failing_model(429, 2, 3);
+        assert_eq!(model.get_response(&[], None, None).await.unwrap(), "done");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn caps_retry_after_at_the_max_delay() {
+        let (model, _) = failing_model(429, 1, 3);
+        let error = HttpStatusError {
+            status: 429,
+            retry_after: Some(Duration::from_secs(3600)),
+            message: "failed".to_string()
+        };
+
+        assert_eq!(model.get_delay(&error, 0), Some(Duration::from_secs_f64(0.001)));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (model, attempts) = failing_model(503, 10, 2);
+        assert!(model.get_response(&[], None, None).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (model, attempts) = failing_model(400, 1, 3);
+        assert!(model.get_response(&[], None, None).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    /// Answers the first `limited` requests with a 429, and the rest with a chat completion.
+    fn serve_rate_limited(limited: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    if let Some(length) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap_or(0);
+                    }
+                }
+                let mut body = vec![0; content_length];
+                let _ = reader.read_exact(&mut body);
+
+                let response = if counter.fetch_add(1, Ordering::SeqCst) < limited {
+                    let body = r#"{"error": {"message": "slow down"}}"#;
+                    format!("HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+                } else {
+                    let body = r#"{"choices": [{"message": {"role": "assistant", "content": "hello"}}]}"#;
+                    format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        (address, requests)
+    }
+
+    #[tokio::test]
+    async fn retries_a_server_that_rate_limits() {
+        let (address, requests) = serve_rate_limited(2);
+        let model = RetryModel {
+            model: create_model_compatible().create(serde_json::json!({
+                "api base": address,
+                "model": "test"
+            })).unwrap(),
+            policy: policy(3),
+            limiter: None
+        };
+
+        let response = model.get_response(&[ Message::User("hi".to_string()) ], None, None).await.unwrap();
+        assert_eq!(response, "hello");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_requests() {
+        let limiter = RateLimiter::new(&RateLimit { concurrency: Some(1), requests_per_minute: None });
+
+        let permit = limiter.acquire().await;
+        assert!(timeout(Duration::from_millis(50), limiter.acquire()).await.is_err());
+
+        drop(permit);
+        assert!(timeout(Duration::from_millis(50), limiter.acquire()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn limits_requests_per_minute() {
+        let limiter = RateLimiter::new(&RateLimit { concurrency: None, requests_per_minute: Some(2) });
+
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert!(timeout(Duration::from_millis(50), limiter.acquire()).await.is_err());
+    }
+
+    #[test]
+    fn shares_limiters_between_configs() {
+        let limit = RateLimit { concurrency: Some(2), requests_per_minute: None };
+        let first = get_rate_limiter("Test Provider", &limit);
+        assert!(Arc::ptr_eq(&first, &get_rate_limiter("test provider", &limit)));
+
+        let changed = RateLimit { concurrency: Some(3), requests_per_minute: None };
+        assert!(!Arc::ptr_eq(&first, &get_rate_limiter("test provider", &changed)));
+    }
+}
@@ -1,7 +1,7 @@
-use std::error::Error;
+use std::{error::Error, io::{self, Write}};
 
 use colored::Colorize;
-use crate::auto::{Update, DynamicUpdate, StaticUpdate, log_yaml, NamedAsset};
+use crate::{Usage, auto::{Update, DynamicUpdate, StaticUpdate, UsageUpdate, StreamUpdate, log_yaml, NamedAsset}};
 
 pub fn log_update(update: &Update) -> Result<(), Box<dyn Error>> {
     match update {
@@ -18,6 +18,12 @@ pub fn log_update(update: &Update) -> Result<(), Box<dyn Error>> {
                     println!();
                     log_yaml(&thoughts)?;
                     println!();
+                },
+                DynamicUpdate::BudgetExhausted(reason) => {
+                    println!("{} | {}", "Dynamic Agent".blue().bold(), "Stopping Early".white());
+                    println!();
+                    println!("Stopping because {reason}.");
+                    println!();
                 }
             }
         },
@@ -35,6 +41,12 @@ pub fn log_update(update: &Update) -> Result<(), Box<dyn Error>> {
                     log_yaml(&step)?;
                     println!();
                 },
+                StaticUpdate::Script(script) => {
+                    println!("{} | {}", "Static Agent".yellow().bold(), "Wrote Script".white());
+                    println!();
+                    println!("{script}");
+                    println!();
+                },
                 StaticUpdate::Thoughts(thoughts) => {
                     println!("{} | {}", "Static Agent".yellow().bold(), "Running Step".white());
                     println!();
@@ -53,6 +65,14 @@ pub fn log_update(update: &Update) -> Result<(), Box<dyn Error>> {
                     println!("{asset}");
                     println!();
                 },
+                StaticUpdate::Reflected(reflections) => {
+                    println!("{} | {}", "Static Agent".yellow().bold(), "Reflected".white());
+                    println!();
+                    for insight in &reflections.insights {
+                        println!("{} {insight}", "-".white());
+                    }
+                    println!();
+                },
                 StaticUpdate::AddedAsset(asset) => {
                     println!("{} | {}", "Static Agent".yellow().bold(), "Added Asset".white());
                     println!();
@@ -63,8 +83,47 @@ pub fn log_update(update: &Update) -> Result<(), Box<dyn Error>> {
                 },
                 _ => {}
             }
+        },
+        Update::Usage(update) => {
+            // Individual calls are too noisy for the terminal, so only the summary is shown.
+            if let UsageUpdate::Summary(summary) = update {
+                println!("{} | {}", "Usage".green().bold(), "Run Summary".white());
+                println!();
+
+                let mut agents = summary.agents.iter().collect::<Vec<_>>();
+                agents.sort_by_key(|(name, _)| name.as_str());
+
+                for (name, usage) in agents {
+                    println!("{} {}: {}", "-".black(), name, format_usage(usage));
+                }
+                println!("{}: {}", "Total".bold(), format_usage(&summary.total));
+                println!();
+            }
+        },
+        Update::Stream(update) => {
+            match update {
+                StreamUpdate::Token(token) => {
+                    print!("{}", token.bright_black());
+                    io::stdout().flush()?;
+                },
+                StreamUpdate::End => {
+                    println!();
+                    println!();
+                }
+            }
+        },
+        Update::NewRun(run_id) => {
+            println!("{}: {}", "Run".blue(), run_id);
+            println!();
         }
     }
 
     Ok(())
+}
+
+pub fn format_usage(usage: &Usage) -> String {
+    format!(
+        "{} tokens ({} prompt, {} completion), ${:.4}",
+        usage.total_tokens(), usage.prompt_tokens, usage.completion_tokens, usage.cost
+    )
 }
\ No newline at end of file
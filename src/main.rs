@@ -1,5 +1,6 @@
-use std::{error::Error, fmt::Display, process, fs};
+use std::{error::Error, fmt::Display, process, fs, env, io::{self, Write}};
 use colored::Colorize;
+use tokio::runtime::Runtime;
 
 pub use smartgpt::*;
 
@@ -14,7 +15,51 @@ impl Display for NoThoughtError {
 
 impl Error for NoThoughtError {}
 
+#[derive(Debug, Clone)]
+pub struct DeclinedError;
+
+impl Display for DeclinedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the user declined to run this action.")
+    }
+}
+
+impl Error for DeclinedError {}
+
+fn confirm_action(action: &Action) -> Result<(), DisallowedAction> {
+    let confirm = || -> Result<bool, Box<dyn Error>> {
+        // Prompts go to stderr, so they don't end up in the events written to stdout.
+        eprintln!("{} | {}", "Approval".red().bold(), "Action Needs Permission".white());
+        eprintln!();
+        eprintln!("{}", serde_yaml::to_string(action)?);
+        eprint!("Allow this action? (y/n) ");
+        io::stderr().flush()?;
+
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        eprintln!();
+
+        Ok(answer.trim().eq_ignore_ascii_case("y"))
+    };
+
+    match confirm() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(DisallowedAction(Box::new(DeclinedError))),
+        Err(err) => Err(DisallowedAction(err))
+    }
+}
+
+fn get_flag(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|ind| args.get(ind + 1))
+        .cloned()
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let command = args.first().map(|el| el.as_str());
+
     let config = fs::read_to_string("config.yml");
 
     let config = match config {
@@ -31,11 +76,35 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let (task, mut smartgpt) = load_config(&config)?;
 
-    print!("\x1B[2J\x1B[1;1H");
-    println!("{}: {}", "Personality".blue(), smartgpt.personality);
-    println!("{}: {}", "Task".blue(), task);
+    if command == Some("memory") {
+        let mut context = smartgpt.context.lock().unwrap();
+        if let Err(err) = run_memory_command(&mut context, &args[1..]) {
+            println!("{}: {err}", "Error".red());
+            process::exit(1);
+        }
+        return Ok(());
+    }
 
-    println!("{}:", "Plugins".blue());
+    let task = match get_flag(&args, "--resume") {
+        Some(run_id) => smartgpt.resume(&run_id)?,
+        None => task
+    };
+    let run_id = smartgpt.context.lock().unwrap().run.id.clone();
+
+    // `--events -` writes JSON Lines to stdout, so nothing else should be printed there.
+    let events = get_flag(&args, "--events");
+    let quiet = events.as_deref() == Some("-");
+
+    if !quiet {
+        print!("\x1B[2J\x1B[1;1H");
+        println!("{}: {}", "Personality".blue(), smartgpt.personality);
+        if smartgpt.auto_type == AutoType::Runner && command != Some("serve") {
+            println!("{}: {}", "Task".blue(), task);
+            println!("{}: {}", "Run".blue(), run_id);
+        }
+
+        println!("{}:", "Plugins".blue());
+    }
     let mut exit_dependency_error = false;
 
     let context = smartgpt.context.lock().unwrap();
@@ -63,7 +132,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }).collect::<Vec<_>>()
         };
 
-        if !exit_dependency_error {
+        if !exit_dependency_error && !quiet {
             print!("{} {} (tools: ", "-".black(), plugin.name);
             for (ind, tool) in tools.iter().enumerate() {
                 print!("{}", tool);
@@ -79,15 +148,64 @@ fn main() -> Result<(), Box<dyn Error>> {
         process::exit(1);
     }
 
-    println!();
+    if !quiet {
+        println!();
+    }
 
     drop(context);
 
-    smartgpt.run_task( 
-        &task, 
-        &mut |_| Ok(()), 
-        &mut log_update
-    )?;
+    if command == Some("serve") {
+        let port = match get_flag(&args, "--port") {
+            Some(port) => port.parse()?,
+            None => 3001
+        };
+
+        println!("{}: Listening on port {}.", "Server".blue(), port);
+        return Runtime::new()?.block_on(serve(config, port));
+    }
+
+    let sinks: Vec<Box<dyn UpdateSink>> = match events.as_deref() {
+        Some("-") => vec![ Box::new(JsonLinesSink::stdout()) ],
+        Some(path) => vec![ Box::new(LogSink), Box::new(JsonLinesSink::file(path)?) ],
+        None => vec![ Box::new(LogSink) ]
+    };
+    let mut emitter = UpdateEmitter::new(&run_id, sinks);
+
+    match smartgpt.auto_type {
+        AutoType::Runner => {
+            smartgpt.run_task( 
+                &task, 
+                &mut confirm_action, 
+                &mut |update| emitter.emit(update)
+            )?;
+        },
+        AutoType::Assistant => {
+            loop {
+                print!("{} ", ">".blue());
+                io::stdout().flush()?;
+
+                let mut message = String::new();
+                if io::stdin().read_line(&mut message)? == 0 {
+                    break;
+                }
+
+                let message = message.trim();
+                if message.is_empty() {
+                    continue;
+                }
+
+                let response = smartgpt.chat(
+                    message, 
+                    &mut confirm_action, 
+                    &mut |update| emitter.emit(update)
+                )?;
+
+                println!();
+                println!("{}: {}", "Assistant".blue(), response);
+                println!();
+            }
+        }
+    }
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,325 @@
+use std::{collections::{BinaryHeap, HashSet}, cmp::{Ordering, Reverse}, io::{self, Read, Write}};
+
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    distance: f32,
+    id: usize
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance.total_cmp(&other.distance) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+/// A hierarchical navigable small world graph, for finding the nearest neighbors of a vector
+/// without comparing it to every other one. It only keeps the graph, so distances are given
+/// by the caller.
+pub struct Hnsw {
+    /// How many neighbors each node keeps above the bottom layer. The bottom layer keeps twice as many.
+    pub m: usize,
+    pub ef_construction: usize,
+    pub entry: Option<usize>,
+    /// The neighbors of each node, by layer.
+    pub nodes: Vec<Vec<Vec<u32>>>
+}
+
+impl Hnsw {
+    pub fn new(m: usize, ef_construction: usize) -> Self {
+        Self {
+            m: m.max(2),
+            ef_construction,
+            entry: None,
+            nodes: vec![]
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn get_max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 { self.m * 2 } else { self.m }
+    }
+
+    fn get_random_level(&self) -> usize {
+        let uniform: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.);
+        (-uniform.ln() / (self.m as f64).ln()) as usize
+    }
+
+    /// Walks towards the query on one layer, keeping the `ef` closest nodes found.
+    /// Gives them closest first.
+    fn search_layer(&self, distance: &impl Fn(usize) -> f32, entries: &[usize], ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entries.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+
+        for &id in entries {
+            let candidate = Candidate { distance: distance(id), id };
+            candidates.push(Reverse(candidate));
+            found.push(candidate);
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = found.peek().map(|el: &Candidate| el.distance).unwrap_or(f32::INFINITY);
+            if candidate.distance > furthest && found.len() >= ef {
+                break;
+            }
+
+            for &neighbor in self.nodes[candidate.id].get(layer).into_iter().flatten() {
+                let neighbor = neighbor as usize;
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let neighbor = Candidate { distance: distance(neighbor), id: neighbor };
+                let furthest = found.peek().map(|el| el.distance).unwrap_or(f32::INFINITY);
+                if found.len() < ef || neighbor.distance < furthest {
+                    candidates.push(Reverse(neighbor));
+                    found.push(neighbor);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    /// Goes down from the top layer to `layer`, following the closest node on each.
+    fn descend(&self, distance: &impl Fn(usize) -> f32, layer: usize) -> Option<usize> {
+        let entry = self.entry?;
+        let mut closest = entry;
+
+        for current in (layer..self.nodes[entry].len()).rev() {
+            if let Some(candidate) = self.search_layer(distance, &[ closest ], 1, current).first() {
+                closest = candidate.id;
+            }
+        }
+
+        Some(closest)
+    }
+
+    /// Adds the next node. `distance` gives the distance between two nodes, including the new one.
+    pub fn insert(&mut self, distance: &impl Fn(usize, usize) -> f32) {
+        let id = self.nodes.len();
+        let level = self.get_random_level();
+        self.nodes.push(vec![vec![]; level + 1]);
+
+        let to_new = |other: usize| distance(id, other);
+        let top = match self.entry {
+            Some(entry) => self.nodes[entry].len() - 1,
+            None => {
+                self.entry = Some(id);
+                return;
+            }
+        };
+
+        let mut entries = match self.descend(&to_new, level + 1) {
+            Some(closest) => vec![ closest ],
+            None => return
+        };
+
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&to_new, &entries, self.ef_construction, layer);
+            let max_neighbors = self.get_max_neighbors(layer);
+
+            let neighbors = found.iter()
+                .filter(|el| el.id != id)
+                .take(max_neighbors)
+                .map(|el| el.id as u32)
+                .collect::<Vec<_>>();
+
+            for &neighbor in &neighbors {
+                let neighbor = neighbor as usize;
+                let links = &mut self.nodes[neighbor][layer];
+                links.push(id as u32);
+
+                // Keep only the closest links once there are too many.
+                if links.len() > max_neighbors {
+                    let mut ranked = links.iter()
+                        .map(|&el| Candidate { distance: distance(neighbor, el as usize), id: el as usize })
+                        .collect::<Vec<_>>();
+                    ranked.sort();
+                    *links = ranked.into_iter().take(max_neighbors).map(|el| el.id as u32).collect();
+                }
+            }
+
+            self.nodes[id][layer] = neighbors;
+            entries = found.into_iter().map(|el| el.id).collect();
+        }
+
+        if level > top {
+            self.entry = Some(id);
+        }
+    }
+
+    /// Finds about the `count` closest nodes to a query, closest first, with their distances.
+    /// A higher `ef` finds them more reliably, but takes longer.
+    pub fn search(&self, distance: &impl Fn(usize) -> f32, count: usize, ef: usize) -> Vec<(usize, f32)> {
+        let Some(closest) = self.descend(distance, 1) else {
+            return vec![];
+        };
+
+        self.search_layer(distance, &[ closest ], ef.max(count), 0)
+            .into_iter()
+            .take(count)
+            .map(|el| (el.id, el.distance))
+            .collect()
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let write_u32 = |writer: &mut dyn Write, value: usize| writer.write_all(&(value as u32).to_le_bytes());
+
+        write_u32(writer, self.m)?;
+        write_u32(writer, self.ef_construction)?;
+        write_u32(writer, self.entry.map(|el| el + 1).unwrap_or(0))?;
+        write_u32(writer, self.nodes.len())?;
+
+        for node in &self.nodes {
+            write_u32(writer, node.len())?;
+            for links in node {
+                write_u32(writer, links.len())?;
+                for &link in links {
+                    writer.write_all(&link.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let read_u32 = |reader: &mut dyn Read| {
+            let mut bytes = [0u8; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok::<_, io::Error>(u32::from_le_bytes(bytes) as usize)
+        };
+
+        let m = read_u32(reader)?;
+        let ef_construction = read_u32(reader)?;
+        let entry = read_u32(reader)?.checked_sub(1);
+        let count = read_u32(reader)?;
+
+        let mut nodes = Vec::with_capacity(count);
+        for _ in 0..count {
+            let levels = read_u32(reader)?;
+            let mut node = Vec::with_capacity(levels);
+            for _ in 0..levels {
+                let len = read_u32(reader)?;
+                let mut links = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let link = read_u32(reader)?;
+                    if link >= count {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "the index links to a missing node"));
+                    }
+                    links.push(link as u32);
+                }
+                node.push(links);
+            }
+            nodes.push(node);
+        }
+
+        if entry.map(|el| el >= count).unwrap_or(count > 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the index has no entry point"));
+        }
+
+        Ok(Self { m, ef_construction, entry, nodes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::Hnsw;
+
+    fn get_points(count: usize, seed: u64) -> Vec<Vec<f32>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count).map(|_| (0..16).map(|_| rng.gen_range(-1f32..1.)).collect()).collect()
+    }
+
+    fn get_distance(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+    }
+
+    fn build(points: &[Vec<f32>]) -> Hnsw {
+        let mut index = Hnsw::new(16, 100);
+        for _ in points {
+            index.insert(&|a, b| get_distance(&points[a], &points[b]));
+        }
+        index
+    }
+
+    fn exact_search(points: &[Vec<f32>], query: &[f32], count: usize) -> Vec<usize> {
+        let mut found = (0..points.len()).collect::<Vec<_>>();
+        found.sort_by(|&a, &b| get_distance(&points[a], query).total_cmp(&get_distance(&points[b], query)));
+        found.truncate(count);
+        found
+    }
+
+    #[test]
+    fn finds_nearly_the_same_neighbors_as_exact_search() {
+        let points = get_points(2000, 1);
+        let index = build(&points);
+
+        let mut matched = 0;
+        let queries = get_points(50, 2);
+        for query in &queries {
+            let exact = exact_search(&points, query, 10);
+            let found = index.search(&|id| get_distance(&points[id], query), 10, 100);
+
+            assert!(found.windows(2).all(|el| el[0].1 <= el[1].1));
+            matched += found.iter().filter(|(id, _)| exact.contains(id)).count();
+        }
+
+        let recall = matched as f32 / (queries.len() * 10) as f32;
+        assert!(recall >= 0.9, "recall@10 was {recall}");
+    }
+
+    #[test]
+    fn reads_what_it_wrote() {
+        let points = get_points(300, 3);
+        let index = build(&points);
+
+        let mut bytes = vec![];
+        index.write(&mut bytes).unwrap();
+        let read = Hnsw::read(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(read.m, index.m);
+        assert_eq!(read.ef_construction, index.ef_construction);
+        assert_eq!(read.entry, index.entry);
+        assert_eq!(read.nodes, index.nodes);
+    }
+
+    #[test]
+    fn rejects_a_truncated_index() {
+        let points = get_points(100, 4);
+        let mut bytes = vec![];
+        build(&points).write(&mut bytes).unwrap();
+
+        bytes.truncate(bytes.len() / 2);
+        assert!(Hnsw::read(&mut bytes.as_slice()).is_err());
+    }
+}
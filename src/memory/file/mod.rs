@@ -0,0 +1,5 @@
+mod hnsw;
+mod system;
+
+pub use hnsw::*;
+pub use system::*;
\ No newline at end of file
@@ -0,0 +1,828 @@
+use std::{collections::HashMap, error::Error, fs::{self, File, OpenOptions}, io::{BufRead, BufReader, BufWriter, Read, Write, ErrorKind}, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{LLM, Memory, MemoryProvider, MemorySystem, RelevantMemory, MemoryMetadata, MemoryFilter, Hnsw, KeywordIndex};
+
+/// Pools up to this size are searched exactly, since comparing against every memory is fast enough.
+const EXACT_SEARCH_LIMIT: usize = 10_000;
+
+/// The index is saved after this many memories, or a twentieth of the index, are added to it.
+/// The rest are indexed again on load.
+const INDEX_SAVE_INTERVAL: usize = 100;
+
+#[derive(Serialize, Deserialize)]
+enum LogEntry {
+    /// `decay` is the total decay when the memory was added. Logs written before memories had
+    /// their own IDs leave out `id`, and their memories are numbered in order.
+    #[serde(rename = "add")] Add {
+        #[serde(default)] id: Option<usize>,
+        content: String, recall: f32, recency: f32, decay: f64,
+        #[serde(default)] metadata: MemoryMetadata
+    },
+    #[serde(rename = "recall")] Recall { id: usize, recall: f32 },
+    #[serde(rename = "delete")] Delete { ids: Vec<usize> },
+    /// The total decay so far, as the sum of the logarithms of every decay factor.
+    #[serde(rename = "decay")] Decay(f64),
+    /// The next ID to give out, so that deleted memories' IDs aren't reused once they're compacted away.
+    #[serde(rename = "next id")] NextId(usize)
+}
+
+struct StoredMemory {
+    id: usize,
+    content: String,
+    recall: f32,
+    recency: f32,
+    decay: f64,
+    metadata: MemoryMetadata,
+    embedding: Vec<f32>,
+    norm: f32,
+    deleted: bool
+}
+
+/// Memories kept in a directory: a log of changes in `memories.jsonl`, their embeddings in
+/// `vectors.bin`, and a search index over them in `index.bin`.
+pub struct MemoryStore {
+    pub directory: PathBuf,
+    /// Every memory in the order it was written, which is also its node in the index.
+    /// Deleted memories stay here until the files are compacted.
+    memories: Vec<StoredMemory>,
+    /// Where each memory that hasn't been deleted is in `memories`, by ID.
+    slots: HashMap<usize, usize>,
+    next_id: usize,
+    /// Decaying every memory would mean rewriting all of them, so recency is decayed
+    /// from when each memory was added instead.
+    decay: f64,
+    index: Hnsw,
+    /// The words of every memory that hasn't been deleted, by ID.
+    keywords: KeywordIndex,
+    /// How many memories the saved index has.
+    indexed: usize,
+    log_entries: usize,
+    /// How many times each memory system sharing the store asked for its memories to decay, by handle.
+    decay_requests: Vec<(usize, u64)>,
+    next_handle: usize,
+    decays_applied: u64,
+    log: File,
+    vectors: File
+}
+
+fn get_norm(embedding: &[f32]) -> f32 {
+    embedding.iter().map(|el| el * el).sum::<f32>().sqrt()
+}
+
+fn get_distance(a: &StoredMemory, b: &[f32], b_norm: f32) -> f32 {
+    if a.norm == 0. || b_norm == 0. {
+        return 1.;
+    }
+
+    let dot_product = a.embedding.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    1. - dot_product / (a.norm * b_norm)
+}
+
+fn read_vectors(path: &Path) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    let mut reader = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(Box::new(err))
+    };
+
+    let mut vectors = vec![];
+    let mut len = [0u8; 4];
+    // A vector that was cut off while being written is left out.
+    while reader.read_exact(&mut len).is_ok() {
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize * 4];
+        if reader.read_exact(&mut bytes).is_err() {
+            break;
+        }
+
+        vectors.push(bytes.chunks_exact(4).map(|el| f32::from_le_bytes([ el[0], el[1], el[2], el[3] ])).collect());
+    }
+
+    Ok(vectors)
+}
+
+fn write_vector(writer: &mut impl Write, embedding: &[f32]) -> Result<(), Box<dyn Error>> {
+    writer.write_all(&(embedding.len() as u32).to_le_bytes())?;
+    for value in embedding {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn open_append(path: &Path) -> Result<File, Box<dyn Error>> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+impl MemoryStore {
+    pub fn open(directory: &Path) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(directory)?;
+
+        let log_path = directory.join("memories.jsonl");
+        let vectors_path = directory.join("vectors.bin");
+
+        let mut memories = vec![];
+        let mut slots = HashMap::new();
+        let mut next_id = 0;
+        let mut decay = 0.;
+        let mut log_entries = 0;
+        let mut ended_early = false;
+        if log_path.exists() {
+            for line in BufReader::new(File::open(&log_path)?).lines() {
+                // A line that was cut off while being written ends the log, and the log is
+                // rewritten without it so that new entries aren't appended after it.
+                let Ok(entry) = serde_json::from_str::<LogEntry>(&line?) else {
+                    ended_early = true;
+                    break;
+                };
+                log_entries += 1;
+
+                match entry {
+                    LogEntry::Add { id, content, recall, recency, decay, metadata } => {
+                        let id = id.unwrap_or(next_id);
+                        next_id = next_id.max(id + 1);
+                        slots.insert(id, memories.len());
+                        memories.push(StoredMemory {
+                            id, content, recall, recency, decay, metadata,
+                            embedding: vec![],
+                            norm: 0.,
+                            deleted: false
+                        });
+                    },
+                    LogEntry::Recall { id, recall } => if let Some(&slot) = slots.get(&id) {
+                        memories[slot].recall = recall;
+                    },
+                    LogEntry::Delete { ids } => for id in ids {
+                        if let Some(slot) = slots.remove(&id) {
+                            memories[slot].deleted = true;
+                        }
+                    },
+                    LogEntry::Decay(total) => decay = total,
+                    LogEntry::NextId(id) => next_id = next_id.max(id)
+                }
+            }
+        }
+
+        let vectors = read_vectors(&vectors_path)?;
+        let consistent = !ended_early && vectors.len() == memories.len();
+        memories.truncate(vectors.len());
+        slots.retain(|_, slot| *slot < memories.len());
+        for (memory, embedding) in memories.iter_mut().zip(vectors) {
+            memory.norm = get_norm(&embedding);
+            memory.embedding = embedding;
+        }
+
+        let mut keywords = KeywordIndex::default();
+        for memory in memories.iter().filter(|el| !el.deleted) {
+            keywords.insert(memory.id, &memory.content);
+        }
+
+        let index = File::open(directory.join("index.bin")).ok()
+            .and_then(|file| Hnsw::read(&mut BufReader::new(file)).ok())
+            .filter(|index| index.len() <= memories.len())
+            .unwrap_or_else(|| Hnsw::new(16, 100));
+
+        let mut store = Self {
+            directory: directory.to_path_buf(),
+            indexed: index.len(),
+            memories,
+            slots,
+            next_id,
+            decay,
+            index,
+            keywords,
+            log_entries,
+            decay_requests: vec![],
+            next_handle: 0,
+            decays_applied: 0,
+            log: open_append(&log_path)?,
+            vectors: open_append(&vectors_path)?
+        };
+
+        // Rewriting the files drops anything left over from an interrupted write, and
+        // the recall updates and deleted memories that built up.
+        if !consistent || store.needs_compacting() {
+            store.compact()?;
+        }
+
+        let unindexed = store.index.len()..store.memories.len();
+        if !unindexed.is_empty() {
+            for _ in unindexed {
+                store.index_next();
+            }
+            store.save_index()?;
+        }
+
+        Ok(store)
+    }
+
+    fn needs_compacting(&self) -> bool {
+        let deleted = self.memories.len() - self.slots.len();
+        self.log_entries > self.memories.len() * 2 + 100 || deleted * 4 > self.memories.len()
+    }
+
+    fn compact(&mut self) -> Result<(), Box<dyn Error>> {
+        let log_path = self.directory.join("memories.jsonl");
+        let vectors_path = self.directory.join("vectors.bin");
+        let index_path = self.directory.join("index.bin");
+        let log_temp = self.directory.join("memories.jsonl.tmp");
+        let vectors_temp = self.directory.join("vectors.bin.tmp");
+
+        // Dropping deleted memories moves the rest to other nodes, so the saved index
+        // is removed first, in case it's loaded again before it's rebuilt.
+        let dropped = self.slots.len() < self.memories.len();
+        if dropped {
+            match fs::remove_file(&index_path) {
+                Ok(()) => {},
+                Err(err) if err.kind() == ErrorKind::NotFound => {},
+                Err(err) => return Err(Box::new(err))
+            }
+            self.memories.retain(|el| !el.deleted);
+        }
+
+        let mut log = BufWriter::new(File::create(&log_temp)?);
+        let mut vectors = BufWriter::new(File::create(&vectors_temp)?);
+
+        for memory in &self.memories {
+            let entry = LogEntry::Add {
+                id: Some(memory.id),
+                content: memory.content.clone(),
+                recall: memory.recall,
+                recency: memory.recency,
+                decay: memory.decay,
+                metadata: memory.metadata.clone()
+            };
+            writeln!(log, "{}", serde_json::to_string(&entry)?)?;
+            write_vector(&mut vectors, &memory.embedding)?;
+        }
+        writeln!(log, "{}", serde_json::to_string(&LogEntry::NextId(self.next_id))?)?;
+        writeln!(log, "{}", serde_json::to_string(&LogEntry::Decay(self.decay))?)?;
+
+        log.into_inner()?.sync_all()?;
+        vectors.into_inner()?.sync_all()?;
+        fs::rename(vectors_temp, &vectors_path)?;
+        fs::rename(log_temp, &log_path)?;
+
+        self.log_entries = self.memories.len() + 2;
+        self.log = open_append(&log_path)?;
+        self.vectors = open_append(&vectors_path)?;
+
+        if dropped {
+            self.slots = self.memories.iter().enumerate().map(|(slot, el)| (el.id, slot)).collect();
+            self.index = Hnsw::new(self.index.m, self.index.ef_construction);
+            for _ in 0..self.memories.len() {
+                self.index_next();
+            }
+            self.save_index()?;
+        }
+
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &LogEntry) -> Result<(), Box<dyn Error>> {
+        writeln!(self.log, "{}", serde_json::to_string(entry)?)?;
+        self.log_entries += 1;
+        Ok(())
+    }
+
+    fn index_next(&mut self) {
+        let memories = &self.memories;
+        self.index.insert(&|a, b| get_distance(&memories[a], &memories[b].embedding, memories[b].norm));
+    }
+
+    fn save_index(&mut self) -> Result<(), Box<dyn Error>> {
+        let path = self.directory.join("index.bin");
+        let temp = self.directory.join("index.bin.tmp");
+
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        self.index.write(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(temp, path)?;
+
+        self.indexed = self.index.len();
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn add(&mut self, content: &str, embedding: Vec<f32>, metadata: &MemoryMetadata) -> Result<usize, Box<dyn Error>> {
+        self.insert(content, embedding, 1., 1., metadata)
+    }
+
+    /// Adds a memory that already has a recall and recency, like one that was exported.
+    pub fn import(&mut self, memory: &Memory) -> Result<usize, Box<dyn Error>> {
+        self.insert(&memory.content, memory.embedding.clone(), memory.recall, memory.recency, &memory.metadata)
+    }
+
+    fn insert(&mut self, content: &str, embedding: Vec<f32>, recall: f32, recency: f32, metadata: &MemoryMetadata) -> Result<usize, Box<dyn Error>> {
+        // The vector goes first, so a log entry never refers to a vector that wasn't written.
+        let mut bytes = vec![];
+        write_vector(&mut bytes, &embedding)?;
+        self.vectors.write_all(&bytes)?;
+
+        let id = self.next_id;
+        self.append(&LogEntry::Add {
+            id: Some(id),
+            content: content.to_string(),
+            recall,
+            recency,
+            decay: self.decay,
+            metadata: metadata.clone()
+        })?;
+        self.next_id += 1;
+
+        self.slots.insert(id, self.memories.len());
+        self.keywords.insert(id, content);
+        self.memories.push(StoredMemory {
+            id,
+            content: content.to_string(),
+            recall,
+            recency,
+            decay: self.decay,
+            metadata: metadata.clone(),
+            norm: get_norm(&embedding),
+            embedding,
+            deleted: false
+        });
+
+        self.index_next();
+        if self.index.len() - self.indexed >= INDEX_SAVE_INTERVAL.max(self.indexed / 20) {
+            self.save_index()?;
+        }
+
+        Ok(id)
+    }
+
+    fn to_memory(&self, memory: &StoredMemory) -> Memory {
+        Memory {
+            id: memory.id.to_string(),
+            content: memory.content.clone(),
+            recall: memory.recall,
+            recency: memory.recency * (self.decay - memory.decay).exp() as f32,
+            embedding: memory.embedding.clone(),
+            metadata: memory.metadata.clone()
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<Memory> {
+        let &slot = self.slots.get(&id)?;
+        Some(self.to_memory(&self.memories[slot]))
+    }
+
+    pub fn get_all(&self, filter: &MemoryFilter) -> Vec<Memory> {
+        self.memories.iter()
+            .filter(|el| !el.deleted && filter.matches(&el.metadata))
+            .map(|el| self.to_memory(el))
+            .collect()
+    }
+
+    /// Marks memories as deleted, so the other memories keep their IDs and the index keeps its
+    /// nodes. Once over a quarter of the store is deleted, they're dropped from the files and
+    /// the index is built again.
+    pub fn delete(&mut self, ids: &[usize]) -> Result<(), Box<dyn Error>> {
+        let mut ids = ids.iter().copied().filter(|id| self.slots.contains_key(id)).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        self.append(&LogEntry::Delete { ids: ids.clone() })?;
+        for id in ids {
+            if let Some(slot) = self.slots.remove(&id) {
+                self.memories[slot].deleted = true;
+                self.keywords.remove(id, &self.memories[slot].content);
+            }
+        }
+
+        if self.needs_compacting() {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds the `count` memories closest to the embedding that match the filter, with their cosine similarity.
+    pub fn search(&self, embedding: &[f32], count: usize, filter: &MemoryFilter) -> Vec<(usize, f32)> {
+        let norm = get_norm(embedding);
+        let distance = |slot: usize| get_distance(&self.memories[slot], embedding, norm);
+        let matches = |slot: &usize| !self.memories[*slot].deleted && filter.matches(&self.memories[*slot].metadata);
+
+        let exact_search = || {
+            let mut found = (0..self.memories.len())
+                .filter(matches)
+                .map(|slot| (slot, distance(slot)))
+                .collect::<Vec<_>>();
+            found.sort_by(|a, b| a.1.total_cmp(&b.1));
+            found.truncate(count);
+            found
+        };
+
+        let found = if self.memories.len() <= EXACT_SEARCH_LIMIT {
+            exact_search()
+        } else {
+            // The index doesn't know about metadata or deleted memories, so it's asked for more
+            // than needed, and the memories are compared exactly if too few of those match.
+            let unfiltered = *filter == MemoryFilter::default() && self.slots.len() == self.memories.len();
+            let fetched = if unfiltered { count } else { count * 4 };
+            let found = self.index.search(&distance, fetched, (fetched * 2).max(100))
+                .into_iter()
+                .filter(|(slot, _)| matches(slot))
+                .take(count)
+                .collect::<Vec<_>>();
+
+            if found.len() < count { exact_search() } else { found }
+        };
+
+        found.into_iter()
+            .map(|(slot, distance)| (self.memories[slot].id, 1. - distance))
+            .collect()
+    }
+
+    /// Finds the `count` memories whose words best match the query, with their relevance to the
+    /// embedding, so that exact names and paths are found even when their embeddings aren't close.
+    pub fn search_keywords(&self, query: &str, embedding: &[f32], count: usize, filter: &MemoryFilter) -> Vec<(usize, f32)> {
+        let found = self.keywords.search(query, count, |id| {
+            self.slots.get(&id).map(|&slot| filter.matches(&self.memories[slot].metadata)).unwrap_or(false)
+        });
+
+        let norm = get_norm(embedding);
+        found.into_iter()
+            .map(|(id, _)| (id, 1. - get_distance(&self.memories[self.slots[&id]], embedding, norm)))
+            .collect()
+    }
+
+    pub fn reinforce(&mut self, id: usize) -> Result<(), Box<dyn Error>> {
+        let Some(&slot) = self.slots.get(&id) else {
+            return Ok(());
+        };
+        let memory = &mut self.memories[slot];
+        memory.recall += 1.;
+
+        let recall = memory.recall;
+        self.append(&LogEntry::Recall { id, recall })
+    }
+
+    /// Gives a memory system sharing the store its own handle, for asking for decay.
+    pub fn add_handle(&mut self) -> usize {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.decay_requests.push((handle, self.decays_applied));
+        handle
+    }
+
+    /// Stops waiting on a memory system that no longer uses the store.
+    pub fn remove_handle(&mut self, handle: usize) {
+        self.decay_requests.retain(|(el, _)| *el != handle);
+    }
+
+    /// Every memory system sharing the store asks for decay once a cycle, so it's only
+    /// applied once all of them have.
+    pub fn request_decay(&mut self, handle: usize, decay_factor: f32) -> Result<(), Box<dyn Error>> {
+        if let Some((_, requests)) = self.decay_requests.iter_mut().find(|(el, _)| *el == handle) {
+            *requests += 1;
+        }
+
+        while self.decay_requests.iter().map(|(_, el)| el).min().map(|&el| el > self.decays_applied).unwrap_or(false) {
+            self.decay(decay_factor)?;
+            self.decays_applied += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn decay(&mut self, decay_factor: f32) -> Result<(), Box<dyn Error>> {
+        self.decay += (decay_factor as f64).ln();
+        self.append(&LogEntry::Decay(self.decay))?;
+
+        if self.needs_compacting() {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Agents that keep their memories in the same directory share one store.
+static STORES: Mutex<Vec<(PathBuf, Arc<Mutex<MemoryStore>>)>> = Mutex::new(vec![]);
+
+pub fn open_memory_store(directory: &Path) -> Result<Arc<Mutex<MemoryStore>>, Box<dyn Error>> {
+    fs::create_dir_all(directory)?;
+    let directory = directory.canonicalize()?;
+
+    let mut stores = STORES.lock().unwrap();
+    if let Some((_, store)) = stores.iter().find(|(el, _)| el == &directory) {
+        return Ok(store.clone());
+    }
+
+    let store = Arc::new(Mutex::new(MemoryStore::open(&directory)?));
+    stores.push((directory, store.clone()));
+    Ok(store)
+}
+
+pub struct FileMemorySystem {
+    pub store: Arc<Mutex<MemoryStore>>,
+    pub handle: usize
+}
+
+impl Drop for FileMemorySystem {
+    fn drop(&mut self) {
+        if let Ok(mut store) = self.store.lock() {
+            store.remove_handle(self.handle);
+        }
+    }
+}
+
+#[async_trait]
+impl MemorySystem for FileMemorySystem {
+    async fn store_memory(&mut self, llm: &LLM, memory: &str, metadata: &MemoryMetadata) -> Result<(), Box<dyn Error>> {
+        let embedding = llm.get_base_embed(memory).await?;
+        self.store.lock().unwrap().add(memory, embedding, metadata)?;
+
+        Ok(())
+    }
+
+    async fn get_memory_pool(&mut self, llm: &LLM, memory: &str, min_count: usize, filter: &MemoryFilter) -> Result<Vec<RelevantMemory>, Box<dyn Error>> {
+        let embedding = llm.get_base_embed(memory).await?;
+        let store = self.store.lock().unwrap();
+
+        let mut found = store.search(&embedding, min_count, filter);
+        for (id, relevance) in store.search_keywords(memory, &embedding, min_count, filter) {
+            if !found.iter().any(|(found_id, _)| *found_id == id) {
+                found.push((id, relevance));
+            }
+        }
+
+        let memories = found
+            .into_iter()
+            .flat_map(|(id, relevance)| Some(RelevantMemory {
+                memory: store.get(id)?,
+                relevance
+            }))
+            .collect();
+
+        Ok(memories)
+    }
+
+    async fn reinforce_recall(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut store = self.store.lock().unwrap();
+        for id in ids.iter().flat_map(|el| el.parse::<usize>()) {
+            store.reinforce(id)?;
+        }
+
+        Ok(())
+    }
+
+    async fn decay_recency(&mut self, decay_factor: f32) -> Result<(), Box<dyn Error>> {
+        self.store.lock().unwrap().request_decay(self.handle, decay_factor)
+    }
+
+    async fn get_all_memories(&mut self, filter: &MemoryFilter) -> Result<Vec<Memory>, Box<dyn Error>> {
+        Ok(self.store.lock().unwrap().get_all(filter))
+    }
+
+    async fn delete_memories(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        let ids = ids.iter().flat_map(|el| el.parse::<usize>()).collect::<Vec<_>>();
+        self.store.lock().unwrap().delete(&ids)
+    }
+
+    async fn import_memory(&mut self, memory: &Memory) -> Result<(), Box<dyn Error>> {
+        self.store.lock().unwrap().import(memory)?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileMemoryConfig {
+    pub directory: String
+}
+
+pub struct FileProvider;
+
+impl MemoryProvider for FileProvider {
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    fn get_name(&self) -> String {
+        "file".to_string()
+    }
+
+    fn create(&self, config: Value) -> Result<Box<dyn MemorySystem>, Box<dyn Error>> {
+        let FileMemoryConfig { directory } = serde_json::from_value(config)?;
+        let store = open_memory_store(Path::new(&directory))?;
+        let handle = store.lock().unwrap().add_handle();
+
+        Ok(Box::new(FileMemorySystem { store, handle }))
+    }
+
+    fn get_namespaced_config(&self, config: &Value, namespace: &str) -> Value {
+        let mut config = config.clone();
+        if let Some(directory) = config.get("directory").and_then(|el| el.as_str()) {
+            config["directory"] = Path::new(directory).join(namespace).to_string_lossy().into();
+        }
+        config
+    }
+}
+
+pub fn create_memory_file() -> Box<dyn MemoryProvider> {
+    Box::new(FileProvider)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::{self, OpenOptions}, io::Write, path::PathBuf};
+
+    use crate::{MemoryFilter, MemoryMetadata};
+    use super::MemoryStore;
+
+    fn get_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("smartgpt-file-memory-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn get_contents(store: &MemoryStore) -> Vec<(String, String)> {
+        store.get_all(&MemoryFilter::default()).into_iter().map(|el| (el.id, el.content)).collect()
+    }
+
+    /// Embeddings a little further around a circle for each ID, so neighboring IDs are the closest.
+    fn get_embedding(id: usize) -> Vec<f32> {
+        let angle = id as f32 * 0.2;
+        vec![ angle.cos(), angle.sin() ]
+    }
+
+    fn fill(store: &mut MemoryStore, count: usize) {
+        for id in 0..count {
+            store.add(&format!("memory {id}"), get_embedding(id), &MemoryMetadata::default()).unwrap();
+        }
+    }
+
+    #[test]
+    fn reopens_with_the_same_memories() {
+        let directory = get_directory("reopen");
+        let mut store = MemoryStore::open(&directory).unwrap();
+        fill(&mut store, 5);
+        store.reinforce(2).unwrap();
+        store.decay(0.5).unwrap();
+        store.delete(&[ 4 ]).unwrap();
+        let contents = get_contents(&store);
+        drop(store);
+
+        let mut store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(get_contents(&store), contents);
+        assert_eq!(store.get(2).unwrap().recall, 2.);
+        assert_eq!(store.get(0).unwrap().recency, 0.5);
+        assert!(store.get(4).is_none());
+        assert_eq!(store.search_keywords("memory 2", &get_embedding(0), 1, &MemoryFilter::default())[0].0, 2);
+        assert!(store.search_keywords("memory 4", &get_embedding(0), 10, &MemoryFilter::default()).iter().all(|el| el.0 != 4));
+        assert_eq!(store.add("new", vec![ 0., 1. ], &MemoryMetadata::default()).unwrap(), 5);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_ids_through_deletes_and_compaction() {
+        let directory = get_directory("ids");
+        let mut store = MemoryStore::open(&directory).unwrap();
+        fill(&mut store, 8);
+
+        store.delete(&[ 1 ]).unwrap();
+        assert_eq!(store.memories.len(), 8);
+        assert_eq!(store.index.len(), 8);
+        assert_eq!(store.get(5).unwrap().content, "memory 5");
+
+        // Over a quarter of the store is deleted now, so it's compacted.
+        store.delete(&[ 0, 2, 7 ]).unwrap();
+        assert_eq!(store.memories.len(), 4);
+        assert_eq!(store.index.len(), 4);
+        for id in 3..7 {
+            assert_eq!(store.get(id).unwrap().content, format!("memory {id}"));
+        }
+        assert_eq!(store.search(&get_embedding(5), 1, &MemoryFilter::default())[0].0, 5);
+        drop(store);
+
+        let mut store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.get(6).unwrap().content, "memory 6");
+        assert_eq!(store.add("new", vec![ 0., 1. ], &MemoryMetadata::default()).unwrap(), 8);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn leaves_deleted_memories_out_of_searches() {
+        let directory = get_directory("search");
+        let mut store = MemoryStore::open(&directory).unwrap();
+        fill(&mut store, 6);
+        store.delete(&[ 3 ]).unwrap();
+
+        let mut found = store.search(&get_embedding(3), 2, &MemoryFilter::default()).into_iter().map(|el| el.0).collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, vec![ 2, 4 ]);
+        assert!(store.search_keywords("memory 3", &get_embedding(3), 10, &MemoryFilter::default()).iter().all(|el| el.0 != 3));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn recovers_from_a_truncated_vectors_file() {
+        let directory = get_directory("vectors");
+        let mut store = MemoryStore::open(&directory).unwrap();
+        fill(&mut store, 3);
+        drop(store);
+
+        let vectors = directory.join("vectors.bin");
+        let len = fs::metadata(&vectors).unwrap().len();
+        OpenOptions::new().write(true).open(&vectors).unwrap().set_len(len - 2).unwrap();
+
+        let mut store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(get_contents(&store), vec![ ("0".into(), "memory 0".into()), ("1".into(), "memory 1".into()) ]);
+        assert_eq!(store.index.len(), 2);
+        store.add("new", vec![ 0., 1. ], &MemoryMetadata::default()).unwrap();
+        drop(store);
+
+        let store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(3).unwrap().content, "new");
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn recovers_from_a_cut_off_log() {
+        let directory = get_directory("log");
+        let mut store = MemoryStore::open(&directory).unwrap();
+        fill(&mut store, 3);
+        drop(store);
+
+        // The vector of the next memory was written, but not all of its log entry.
+        let mut vectors = OpenOptions::new().append(true).open(directory.join("vectors.bin")).unwrap();
+        vectors.write_all(&2u32.to_le_bytes()).unwrap();
+        vectors.write_all(&[ 0; 8 ]).unwrap();
+        let mut log = OpenOptions::new().append(true).open(directory.join("memories.jsonl")).unwrap();
+        write!(log, "{{\"add\":{{\"id\":3,\"cont").unwrap();
+
+        let mut store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.add("new", vec![ 0., 1. ], &MemoryMetadata::default()).unwrap(), 3);
+        drop(store);
+
+        let store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.get(3).unwrap().content, "new");
+        assert_eq!(store.get(3).unwrap().embedding, vec![ 0., 1. ]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn recovers_from_a_cut_off_decay() {
+        let directory = get_directory("decay");
+        let mut store = MemoryStore::open(&directory).unwrap();
+        fill(&mut store, 2);
+        drop(store);
+
+        let mut log = OpenOptions::new().append(true).open(directory.join("memories.jsonl")).unwrap();
+        write!(log, "{{\"decay\":-0.6").unwrap();
+
+        let mut store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(store.add("new", get_embedding(2), &MemoryMetadata::default()).unwrap(), 2);
+        drop(store);
+
+        let store = MemoryStore::open(&directory).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(2).unwrap().content, "new");
+        assert_eq!(store.get(0).unwrap().recency, 1.);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn decays_once_every_handle_asks() {
+        let directory = get_directory("handles");
+        let mut store = MemoryStore::open(&directory).unwrap();
+        fill(&mut store, 1);
+
+        let first = store.add_handle();
+        let second = store.add_handle();
+        store.request_decay(first, 0.5).unwrap();
+        assert_eq!(store.get(0).unwrap().recency, 1.);
+
+        // The first handle's earlier request counts once the second stops being waited on.
+        store.remove_handle(second);
+        store.request_decay(first, 0.5).unwrap();
+        assert_eq!(store.get(0).unwrap().recency, 0.25);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
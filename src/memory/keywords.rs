@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+
+/// How quickly repeating a term stops adding to the score.
+const BM25_K1: f32 = 1.2;
+/// How much longer documents are penalized.
+const BM25_B: f32 = 0.75;
+
+/// Splits text into lowercase words. Punctuation separates words, so `src/main.rs` matches
+/// both `main.rs` and `main`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|char: char| !char.is_alphanumeric() && char != '_')
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// The BM25 score of one term in one document. `length` is the document's length over the average.
+fn score_term(count: f32, containing: f32, total: f32, length: f32) -> f32 {
+    let idf = ((total - containing + 0.5) / (containing + 0.5) + 1.).ln();
+    idf * count * (BM25_K1 + 1.) / (count + BM25_K1 * (1. - BM25_B + BM25_B * length))
+}
+
+/// Scores each document against the query with BM25, using the documents given as the corpus.
+/// Documents without any of the query's words score 0.
+pub fn score_keywords(query: &str, documents: &[&str]) -> Vec<f32> {
+    let query_terms = tokenize(query).into_iter().collect::<HashSet<_>>();
+    if query_terms.is_empty() || documents.is_empty() {
+        return vec![0.; documents.len()];
+    }
+
+    let documents = documents.iter().map(|el| tokenize(el)).collect::<Vec<_>>();
+    let average_length = documents.iter().map(|el| el.len()).sum::<usize>() as f32 / documents.len() as f32;
+
+    let mut frequencies = vec![];
+    let mut document_counts: HashMap<&str, usize> = HashMap::new();
+    for document in &documents {
+        let mut frequency: HashMap<&str, f32> = HashMap::new();
+        for word in document.iter().filter(|el| query_terms.contains(*el)) {
+            *frequency.entry(word).or_default() += 1.;
+        }
+        for word in frequency.keys() {
+            *document_counts.entry(word).or_default() += 1;
+        }
+        frequencies.push(frequency);
+    }
+
+    let total = documents.len() as f32;
+    documents.iter()
+        .zip(frequencies)
+        .map(|(document, frequency)| {
+            let length = document.len() as f32 / average_length.max(1.);
+            frequency.iter()
+                .map(|(word, count)| score_term(*count, document_counts[word] as f32, total, length))
+                .sum()
+        })
+        .collect()
+}
+
+/// An inverted index for scoring documents with BM25 without reading every one of them.
+/// Documents are kept by ID, and the whole index is the corpus.
+#[derive(Default)]
+pub struct KeywordIndex {
+    /// The documents each term is in, with how many times it's in them. The number of
+    /// documents is the term's document frequency.
+    postings: HashMap<String, HashMap<usize, u32>>,
+    /// How many words each document has.
+    lengths: HashMap<usize, usize>,
+    total_length: usize
+}
+
+impl KeywordIndex {
+    pub fn len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lengths.is_empty()
+    }
+
+    /// Adds a document. Its ID has to be new to the index.
+    pub fn insert(&mut self, id: usize, text: &str) {
+        let words = tokenize(text);
+        self.lengths.insert(id, words.len());
+        self.total_length += words.len();
+        for word in words {
+            *self.postings.entry(word).or_default().entry(id).or_default() += 1;
+        }
+    }
+
+    /// Removes a document. `text` has to be what it was inserted with.
+    pub fn remove(&mut self, id: usize, text: &str) {
+        let Some(length) = self.lengths.remove(&id) else {
+            return;
+        };
+        self.total_length -= length;
+
+        for word in tokenize(text) {
+            if let Some(documents) = self.postings.get_mut(&word) {
+                documents.remove(&id);
+                if documents.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Finds the `count` documents that best match the query and pass `matches`, best first,
+    /// with their scores. Only documents with at least one of the query's words are looked at.
+    pub fn search(&self, query: &str, count: usize, matches: impl Fn(usize) -> bool) -> Vec<(usize, f32)> {
+        if self.lengths.is_empty() {
+            return vec![];
+        }
+
+        let total = self.lengths.len() as f32;
+        let average_length = self.total_length as f32 / total;
+
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for term in tokenize(query).into_iter().collect::<HashSet<_>>() {
+            let Some(documents) = self.postings.get(&term) else {
+                continue;
+            };
+
+            for (&id, &frequency) in documents {
+                let length = self.lengths[&id] as f32 / average_length.max(1.);
+                *scores.entry(id).or_default() += score_term(frequency as f32, documents.len() as f32, total, length);
+            }
+        }
+
+        let mut found = scores.into_iter()
+            .filter(|(id, _)| matches(*id))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        found.truncate(count);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, score_keywords, KeywordIndex};
+
+    const DOCUMENTS: [&str; 4] = [
+        "Fixed the panic in src/memory/file/system.rs when the log was cut off.",
+        "The user prefers short answers and likes Rust.",
+        "Read the docs at https://docs.rs/redis and wrote a summary.",
+        "Wrote the summary of the memory system to notes.txt."
+    ];
+
+    #[test]
+    fn splits_paths_and_urls_into_lowercase_words() {
+        assert_eq!(tokenize("Edit src/Main.rs, then MY_FILE!"), vec![ "edit", "src", "main", "rs", "then", "my_file" ]);
+        assert_eq!(tokenize("https://docs.rs/redis"), vec![ "https", "docs", "rs", "redis" ]);
+        assert!(tokenize(" ... / ").is_empty());
+    }
+
+    #[test]
+    fn ranks_an_exact_filename_above_unrelated_memories() {
+        let scores = score_keywords("What went wrong in system.rs?", &DOCUMENTS);
+
+        assert!(scores[0] > 0.);
+        assert_eq!(scores[1], 0.);
+        assert!(scores[0] > scores[2] && scores[0] > scores[3]);
+    }
+
+    #[test]
+    fn scores_nothing_without_matching_words() {
+        assert_eq!(score_keywords("kubernetes", &DOCUMENTS), vec![ 0.; 4 ]);
+        assert_eq!(score_keywords("", &DOCUMENTS), vec![ 0.; 4 ]);
+        assert!(score_keywords("memory", &[]).is_empty());
+    }
+
+    #[test]
+    fn rarer_words_count_for_more() {
+        // "summary" is in two documents and "redis" in one, so the one with both ranks first.
+        let scores = score_keywords("redis summary", &DOCUMENTS);
+        assert!(scores[2] > scores[3] && scores[3] > 0.);
+    }
+
+    fn get_index(documents: &[&str]) -> KeywordIndex {
+        let mut index = KeywordIndex::default();
+        for (id, document) in documents.iter().enumerate() {
+            index.insert(id, document);
+        }
+        index
+    }
+
+    #[test]
+    fn index_scores_like_the_whole_corpus() {
+        let index = get_index(&DOCUMENTS);
+        let query = "the memory summary in system.rs";
+        let scores = score_keywords(query, &DOCUMENTS);
+
+        let found = index.search(query, 10, |_| true);
+        assert_eq!(found.len(), scores.iter().filter(|el| **el > 0.).count());
+        for (id, score) in found {
+            assert!((score - scores[id]).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn index_filters_and_limits_results() {
+        let index = get_index(&DOCUMENTS);
+
+        let found = index.search("wrote the summary", 1, |id| id != 3);
+        assert_eq!(found.iter().map(|el| el.0).collect::<Vec<_>>(), vec![ 2 ]);
+        assert!(index.search("kubernetes", 10, |_| true).is_empty());
+    }
+
+    #[test]
+    fn index_forgets_removed_documents() {
+        let mut index = get_index(&DOCUMENTS);
+        index.remove(2, DOCUMENTS[2]);
+        index.remove(2, DOCUMENTS[2]);
+        assert_eq!(index.len(), 3);
+        assert!(index.search("redis", 10, |_| true).is_empty());
+
+        let remaining = get_index(&[ DOCUMENTS[0], DOCUMENTS[1], DOCUMENTS[3] ]);
+        let query = "summary of system.rs";
+        let mut found = index.search(query, 10, |_| true).into_iter().map(|el| el.1).collect::<Vec<_>>();
+        let mut expected = remaining.search(query, 10, |_| true).into_iter().map(|el| el.1).collect::<Vec<_>>();
+        found.sort_by(f32::total_cmp);
+        expected.sort_by(f32::total_cmp);
+        assert_eq!(found, expected);
+    }
+}
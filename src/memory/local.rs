@@ -3,33 +3,48 @@ use std::error::Error;
 use async_trait::async_trait;
 use serde_json::Value;
 
-use crate::{LLM, Memory, MemoryProvider, RelevantMemory, compare_embeddings};
+use crate::{LLM, Memory, MemoryProvider, RelevantMemory, MemoryMetadata, MemoryFilter, compare_embeddings};
 
 use super::MemorySystem;
 
 pub struct LocalMemorySystem {
-    pub memory: Vec<Memory>
+    pub memory: Vec<Memory>,
+    /// Memories can be deleted, so IDs are counted rather than taken from the length.
+    pub next_id: usize
+}
+
+impl LocalMemorySystem {
+    fn push(&mut self, memory: Memory) {
+        self.memory.push(Memory {
+            id: self.next_id.to_string(),
+            ..memory
+        });
+        self.next_id += 1;
+    }
 }
 
 #[async_trait]
 impl MemorySystem for LocalMemorySystem {
-    async fn store_memory(&mut self, llm: &LLM, memory: &str) -> Result<(), Box<dyn Error>> {
-        let embedding = llm.model.get_base_embed(memory).await?;
+    async fn store_memory(&mut self, llm: &LLM, memory: &str, metadata: &MemoryMetadata) -> Result<(), Box<dyn Error>> {
+        let embedding = llm.get_base_embed(memory).await?;
 
-        self.memory.push(Memory {
+        self.push(Memory {
+            id: String::new(),
             content: memory.to_string(),
             recency: 1.,
             recall: 1.,
-            embedding: embedding.clone()
+            embedding: embedding.clone(),
+            metadata: metadata.clone()
         });
 
         Ok(())
     }
 
-    async fn get_memory_pool(&mut self, llm: &LLM, memory: &str, _min_count: usize) -> Result<Vec<RelevantMemory>, Box<dyn Error>> {
-        let embedding = llm.model.get_base_embed(memory).await?;
+    async fn get_memory_pool(&mut self, llm: &LLM, memory: &str, _min_count: usize, filter: &MemoryFilter) -> Result<Vec<RelevantMemory>, Box<dyn Error>> {
+        let embedding = llm.get_base_embed(memory).await?;
     
         let results: Vec<RelevantMemory> = self.memory.iter()
+            .filter(|memory| filter.matches(&memory.metadata))
             .map(|memory| RelevantMemory {
                 memory: memory.clone(),
                 relevance: compare_embeddings(&embedding, &memory.embedding)
@@ -39,6 +54,16 @@ impl MemorySystem for LocalMemorySystem {
         Ok(results)
     }
 
+    async fn reinforce_recall(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        for memory in &mut self.memory {
+            if ids.contains(&memory.id) {
+                memory.recall += 1.;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn decay_recency(&mut self, decay_factor: f32) -> Result<(), Box<dyn Error>> {
         for memory in &mut self.memory {
             memory.recency *= decay_factor;
@@ -46,6 +71,25 @@ impl MemorySystem for LocalMemorySystem {
 
         Ok(())
     }
+
+    async fn get_all_memories(&mut self, filter: &MemoryFilter) -> Result<Vec<Memory>, Box<dyn Error>> {
+        Ok(
+            self.memory.iter()
+                .filter(|memory| filter.matches(&memory.metadata))
+                .cloned()
+                .collect()
+        )
+    }
+
+    async fn delete_memories(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        self.memory.retain(|memory| !ids.contains(&memory.id));
+        Ok(())
+    }
+
+    async fn import_memory(&mut self, memory: &Memory) -> Result<(), Box<dyn Error>> {
+        self.push(memory.clone());
+        Ok(())
+    }
 }
 
 pub struct LocalProvider;
@@ -61,7 +105,8 @@ impl MemoryProvider for LocalProvider {
 
     fn create(&self, _: Value) -> Result<Box<dyn MemorySystem> ,Box<dyn Error> > {
         Ok(Box::new(LocalMemorySystem {
-            memory: vec![]
+            memory: vec![],
+            next_id: 0
         }))
     }
 }
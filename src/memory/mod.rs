@@ -1,4 +1,4 @@
-use std::{error::Error, fmt::Display, cmp::{min}, cmp::Ordering::Equal};
+use std::{error::Error, fmt::Display, cmp::Ordering::Equal};
 use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
@@ -6,9 +6,13 @@ use serde_json::Value;
 mod local;
 mod qdrant;
 mod redis;
+mod file;
+mod keywords;
 pub use local::*;
 pub use qdrant::*;
 pub use self::redis::*;
+pub use file::*;
+pub use keywords::*;
 
 use crate::{LLM};
 
@@ -23,12 +27,67 @@ impl Display for MemorySystemLoadError {
 
 impl Error for MemorySystemLoadError {}
 
+/// How much of a memory's recency is kept after each cycle of the agent.
+pub const RECENCY_DECAY: f32 = 0.99;
+
+/// Where a memory came from.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryMetadata {
+    /// Keeps one project's memories apart from another's.
+    pub namespace: Option<String>,
+    pub run: Option<String>,
+    pub task: Option<String>,
+    /// The tools whose results the memory came from.
+    pub tools: Vec<String>,
+    pub urls: Vec<String>,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64
+}
+
+/// Which memories to recall. Memories have to match every field that's given.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryFilter {
+    pub namespace: Option<String>,
+    pub run: Option<String>,
+    pub task: Option<String>,
+    pub tool: Option<String>,
+    pub url: Option<String>,
+    /// Milliseconds since the Unix epoch, inclusive.
+    pub after: Option<u64>,
+    pub before: Option<u64>
+}
+
+impl MemoryFilter {
+    pub fn matches(&self, metadata: &MemoryMetadata) -> bool {
+        let matches_field = |filter: &Option<String>, value: &Option<String>| {
+            filter.is_none() || filter == value
+        };
+        let matches_list = |filter: &Option<String>, values: &[String]| {
+            filter.as_ref().map(|filter| values.contains(filter)).unwrap_or(true)
+        };
+
+        matches_field(&self.namespace, &metadata.namespace) &&
+            matches_field(&self.run, &metadata.run) &&
+            matches_field(&self.task, &metadata.task) &&
+            matches_list(&self.tool, &metadata.tools) &&
+            matches_list(&self.url, &metadata.urls) &&
+            self.after.map(|after| metadata.timestamp >= after).unwrap_or(true) &&
+            self.before.map(|before| metadata.timestamp <= before).unwrap_or(true)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Memory {
+    /// Identifies the memory within its memory system.
+    pub id: String,
     pub content: String,
+    /// How many times the memory has been retrieved, starting at 1.
     pub recall: f32,
     pub recency: f32,
-    pub embedding: Vec<f32>   
+    pub embedding: Vec<f32>,
+    #[serde(default)] pub metadata: MemoryMetadata
 }
 
 #[derive(Clone)]
@@ -47,7 +106,10 @@ pub struct ScoredMemory {
 pub struct Weights {
     pub recall: f32,
     pub recency: f32,
-    pub relevance: f32
+    /// How close the memory's embedding is to the query's.
+    pub relevance: f32,
+    /// How well the memory's words match the query's, which catches exact names, paths and codes.
+    pub keyword: f32
 }
 
 impl Default for Weights {
@@ -55,52 +117,64 @@ impl Default for Weights {
         Weights {
             recall: 1.,
             recency: 1.,
-            relevance: 1.
+            relevance: 1.,
+            keyword: 1.
         }
     }
 }
 
 #[async_trait]
 pub trait MemorySystem : Send + Sync {
-    async fn store_memory(&mut self, llm: &LLM, memory: &str) -> Result<(), Box<dyn Error>>;
+    async fn store_memory(&mut self, llm: &LLM, memory: &str, metadata: &MemoryMetadata) -> Result<(), Box<dyn Error>>;
 
-    async fn get_memory_pool(&mut self, llm: &LLM, memory: &str, min_count: usize) -> Result<Vec<RelevantMemory>, Box<dyn Error>>;
+    /// Finds at least `min_count` memories related to `memory`, if there are that many matching `filter`.
+    async fn get_memory_pool(&mut self, llm: &LLM, memory: &str, min_count: usize, filter: &MemoryFilter) -> Result<Vec<RelevantMemory>, Box<dyn Error>>;
 
+    /// Gets the `count` best scoring memories, best first, and counts them as recalled.
     async fn get_memories(
         &mut self, llm: &LLM, memory: &str, min_count: usize, 
-        weights: Weights, count: usize
+        weights: Weights, count: usize, filter: &MemoryFilter
     ) -> Result<Vec<Memory>, Box<dyn Error>> {
-        let memory_pool = self.get_memory_pool(llm, memory, min_count).await?;
-        let mut memories = memory_pool.iter()
-            .map(|RelevantMemory { memory, relevance }| {
-                ScoredMemory {
-                    memory: memory.clone(),
-                    score: (
-                        weights.recall * memory.recall +
-                        weights.recency * memory.recency +
-                        weights.relevance * relevance
-                    )
-                }
-            })
-            .collect::<Vec<_>>();
-        memories.sort_by(|a, b| a.memory.recency.partial_cmp(&b.memory.recency).unwrap_or(Equal));
-        let memories = memories.iter()
-            .map(|el| el.memory.clone())
-            .rev()
+        let memory_pool = self.get_memory_pool(llm, memory, min_count, filter).await?;
+        let mut memories = score_memories(&memory_pool, memory, &weights);
+        memories.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Equal));
+
+        let memories = memories.into_iter()
             .take(count)
-            .rev()
+            .map(|el| el.memory)
             .collect::<Vec<_>>();
+
+        let ids = memories.iter().map(|el| el.id.clone()).collect::<Vec<_>>();
+        self.reinforce_recall(&ids).await?;
+
         Ok(memories)
     }
 
+    /// Adds one to the recall of each memory.
+    async fn reinforce_recall(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>>;
+
     async fn decay_recency(
         &mut self,
         decay_factor: f32
     ) -> Result<(), Box<dyn Error>>;
 
-    fn store_memory_sync(&mut self, llm: &LLM, memory: &str) -> Result<(), Box<dyn Error>> {
+    /// Names the store the memories are kept in, when other memory systems can share it without
+    /// knowing, so that it's only decayed once a cycle.
+    fn get_shared_store(&self) -> Option<String> {
+        None
+    }
+
+    /// Lists every memory matching `filter`, without counting them as recalled.
+    async fn get_all_memories(&mut self, filter: &MemoryFilter) -> Result<Vec<Memory>, Box<dyn Error>>;
+
+    async fn delete_memories(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>>;
+
+    /// Adds a memory as it is, with its embedding, recall, recency and metadata. It's given a new ID.
+    async fn import_memory(&mut self, memory: &Memory) -> Result<(), Box<dyn Error>>;
+
+    fn store_memory_sync(&mut self, llm: &LLM, memory: &str, metadata: &MemoryMetadata) -> Result<(), Box<dyn Error>> {
         let runtime = tokio::runtime::Runtime::new().unwrap();
-        runtime.block_on(self.store_memory(llm, memory))
+        runtime.block_on(self.store_memory(llm, memory, metadata))
     }
     
     fn get_memory_pool_sync(
@@ -108,9 +182,10 @@ pub trait MemorySystem : Send + Sync {
         llm: &LLM,
         memory: &str,
         min_count: usize,
+        filter: &MemoryFilter
     ) -> Result<Vec<RelevantMemory>, Box<dyn Error>> {
         let runtime = tokio::runtime::Runtime::new().unwrap();
-        runtime.block_on(self.get_memory_pool(llm, memory, min_count))
+        runtime.block_on(self.get_memory_pool(llm, memory, min_count, filter))
     }
     
     fn get_memories_sync(
@@ -120,9 +195,10 @@ pub trait MemorySystem : Send + Sync {
         min_count: usize,
         weights: Weights,
         count: usize,
+        filter: &MemoryFilter
     ) -> Result<Vec<Memory>, Box<dyn Error>> {
         let runtime = tokio::runtime::Runtime::new().unwrap();
-        runtime.block_on(self.get_memories(llm, memory, min_count, weights, count))
+        runtime.block_on(self.get_memories(llm, memory, min_count, weights, count, filter))
     }
 
     fn decay_recency_sync(
@@ -132,6 +208,21 @@ pub trait MemorySystem : Send + Sync {
         let runtime = tokio::runtime::Runtime::new().unwrap();
         runtime.block_on(self.decay_recency(decay_factor))
     }
+
+    fn get_all_memories_sync(&mut self, filter: &MemoryFilter) -> Result<Vec<Memory>, Box<dyn Error>> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(self.get_all_memories(filter))
+    }
+
+    fn delete_memories_sync(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(self.delete_memories(ids))
+    }
+
+    fn import_memory_sync(&mut self, memory: &Memory) -> Result<(), Box<dyn Error>> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(self.import_memory(memory))
+    }
 }
 
 pub fn memory_from_provider<T : Serialize>(provider: impl MemoryProvider, config: T) -> Result<Box<dyn MemorySystem>, Box<dyn Error>> {
@@ -143,13 +234,59 @@ pub trait MemoryProvider {
     fn is_enabled(&self) -> bool;
     fn get_name(&self) -> String;
     fn create(&self, value: Value) -> Result<Box<dyn MemorySystem>, Box<dyn Error>>;
+
+    /// Changes a config so the memory system it creates keeps its memories apart from the
+    /// original's, under `namespace`.
+    fn get_namespaced_config(&self, config: &Value, _namespace: &str) -> Value {
+        config.clone()
+    }
+}
+
+/// Scales the values to between 0 and 1 within the pool, so no part of the score outweighs
+/// the others just because of how it's measured. Values that are all the same count fully.
+fn normalize(values: &[f32]) -> Vec<f32> {
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+    values.iter()
+        .map(|value| if max - min > f32::EPSILON { (value - min) / (max - min) } else { 1. })
+        .collect()
+}
+
+/// Scores each memory by the weighted sum of its recall, recency, relevance and keyword match
+/// with `query`, each normalized across the pool.
+pub fn score_memories(memory_pool: &[RelevantMemory], query: &str, weights: &Weights) -> Vec<ScoredMemory> {
+    let recall = normalize(&memory_pool.iter().map(|el| el.memory.recall).collect::<Vec<_>>());
+    let recency = normalize(&memory_pool.iter().map(|el| el.memory.recency).collect::<Vec<_>>());
+    let relevance = normalize(&memory_pool.iter().map(|el| el.relevance).collect::<Vec<_>>());
+
+    let contents = memory_pool.iter().map(|el| el.memory.content.as_str()).collect::<Vec<_>>();
+    let keyword = if weights.keyword == 0. {
+        vec![0.; memory_pool.len()]
+    } else {
+        normalize(&score_keywords(query, &contents))
+    };
+
+    memory_pool.iter()
+        .enumerate()
+        .map(|(ind, RelevantMemory { memory, .. })| ScoredMemory {
+            memory: memory.clone(),
+            score: weights.recall * recall[ind] +
+                weights.recency * recency[ind] +
+                weights.relevance * relevance[ind] +
+                weights.keyword * keyword[ind]
+        })
+        .collect()
 }
 
 /// This is an implementation of Cosine Similarity.
-pub fn compare_embeddings(a: &Vec<f32>, b: &Vec<f32>) -> f32 {
+pub fn compare_embeddings(a: &[f32], b: &[f32]) -> f32 {
     let dot_product = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>();
     let norm_a = a.iter().map(|x| x.powi(2)).sum::<f32>().sqrt();
     let norm_b = b.iter().map(|x| x.powi(2)).sum::<f32>().sqrt();
-    let min_length = min(a.len(), b.len()) as f32;
-    dot_product / (norm_a * norm_b * min_length)
+    if norm_a == 0. || norm_b == 0. {
+        return 0.;
+    }
+
+    dot_product / (norm_a * norm_b)
 }
\ No newline at end of file
@@ -1,15 +1,13 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::sync::Arc;
 use std::vec;
 use serde::{Serialize, Deserialize};
-use tokio::sync::Mutex;
 
-use crate::{LLM, Memory, MemoryProvider, RelevantMemory, MemorySystem, init_qdrant_client, create_collection_if_not_exists, convert_to_relevant_memory};
+use crate::{LLM, Memory, MemoryProvider, RelevantMemory, MemorySystem, MemoryMetadata, MemoryFilter, init_qdrant_client, get_qdrant_url, create_collection_if_not_exists, create_point_id, convert_to_relevant_memory, convert_to_memory, get_qdrant_filter};
 
 use qdrant_client::prelude::*;
 use qdrant_client::qdrant::vectors::VectorsOptions;
-use qdrant_client::qdrant::{SearchPoints, PointId, Vectors, Vector, WithPayloadSelector, with_payload_selector, RecommendPoints};
+use qdrant_client::qdrant::{SearchPoints, PointId, Vectors, Vector, WithPayloadSelector, with_payload_selector, ScrollPoints, PointsSelector, PointsIdsList, points_selector::PointsSelectorOneOf};
 use tokio::runtime::Runtime;
 
 use async_trait::async_trait;
@@ -18,12 +16,17 @@ use async_trait::async_trait;
 pub struct QdrantPayload {
     pub content: String,
     pub recall: f32,
-    pub recency: f32
+    /// The recency when the memory was stored. It's decayed from `decay` when it's read.
+    pub recency: f32,
+    /// The collection's total decay when the memory was stored.
+    #[serde(default)] pub decay: f64,
+    /// Kept as top-level fields, so they can be filtered on.
+    #[serde(flatten)] pub metadata: MemoryMetadata
 }
 
 impl QdrantPayload {
-    pub fn new(content: String, recall: f32, recency: f32) -> Self {
-        Self { content, recall, recency }
+    pub fn new(content: String, recall: f32, recency: f32, decay: f64, metadata: MemoryMetadata) -> Self {
+        Self { content, recall, recency, decay, metadata }
     }
 
     pub fn to_memory_map(&self) -> Result<HashMap<String, Value>, serde_json::Error> {
@@ -37,42 +40,65 @@ impl QdrantPayload {
 
 pub struct QdrantMemorySystem {
     client: QdrantClient,
-    latest_point_id: Arc<Mutex<Option<u64>>>,
-    collection_name: String
+    url: String,
+    collection_name: String,
+    /// Keeps the collection's total decay, as the sum of the logarithms of every decay factor, in its only point.
+    decay_collection: String
 }
 
-#[async_trait]
-impl MemorySystem for QdrantMemorySystem {
-    async fn store_memory(&mut self, llm: &LLM, memory: &str) -> Result<(), Box<dyn Error>> {
-        let embedding = llm.model.get_base_embed(memory).await?;
+/// The decay collection for a collection of memories.
+pub fn get_decay_collection(collection: &str) -> String {
+    format!("{collection}-decay")
+}
 
-        let memory_struct = Memory {
-            content: memory.to_string(),
-            recency: 1.0,
-            recall: 1.0,
-            embedding: embedding.clone(),
-        };
+fn get_points_selector(ids: Vec<PointId>) -> PointsSelector {
+    PointsSelector {
+        points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList { ids }))
+    }
+}
+
+fn parse_point_id(id: &str) -> PointId {
+    PointId {
+        point_id_options: Some(match id.parse::<u64>() {
+            Ok(id) => point_id::PointIdOptions::Num(id),
+            Err(_) => point_id::PointIdOptions::Uuid(id.to_string())
+        })
+    }
+}
+
+impl QdrantMemorySystem {
+    async fn get_decay(&self) -> Result<f64, Box<dyn Error>> {
+        let points = self.client.get_points(
+            self.decay_collection.to_string(), &[ PointId::from(0) ], Some(false), Some(true), None
+        ).await?.result;
+
+        let decay = points.first()
+            .and_then(|point| point.payload.get("decay"))
+            .and_then(|value| serde_json::to_value(value).ok())
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.);
+
+        Ok(decay)
+    }
 
+    /// Adds a memory as a new point.
+    async fn upsert_memory(&self, memory: &Memory) -> Result<(), Box<dyn Error>> {
         let payload = QdrantPayload::new(
-            memory_struct.content.clone(),
-            memory_struct.recency,
-            memory_struct.recall
+            memory.content.clone(),
+            memory.recall,
+            memory.recency,
+            self.get_decay().await?,
+            memory.metadata.clone()
         );
 
-        let mut latest_point_id = self.latest_point_id.lock().await;
-        let point_id_val = match *latest_point_id {
-            Some(id) => id + 1,
-            None => 1,
-        };
-        *latest_point_id = Some(point_id_val);
-
+        // Random IDs can't collide with the points already in the collection, even from other processes.
         let point_id = PointId {
-            point_id_options: Some(point_id::PointIdOptions::Num(point_id_val)),
+            point_id_options: Some(point_id::PointIdOptions::Uuid(create_point_id())),
         };
         
         let vectors = Vectors {
             vectors_options: Some(VectorsOptions::Vector(Vector {
-                data: embedding.clone(),
+                data: memory.embedding.clone(),
             })),
         };
 
@@ -91,67 +117,72 @@ impl MemorySystem for QdrantMemorySystem {
         Ok(())
     }
 
+    /// Sets one payload field on many points, with one request for each value.
+    async fn set_values(&self, key: &str, values: Vec<(PointId, f32)>) -> Result<(), Box<dyn Error>> {
+        let mut groups: HashMap<u32, Vec<PointId>> = HashMap::new();
+        for (id, value) in values {
+            groups.entry(value.to_bits()).or_default().push(id);
+        }
+
+        for (value, ids) in groups {
+            let mut payload = Payload::new();
+            payload.insert(key, f32::from_bits(value) as f64);
+
+            self.client.set_payload(self.collection_name.to_string(), &get_points_selector(ids), payload, None).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MemorySystem for QdrantMemorySystem {
+    async fn store_memory(&mut self, llm: &LLM, memory: &str, metadata: &MemoryMetadata) -> Result<(), Box<dyn Error>> {
+        let embedding = llm.get_base_embed(memory).await?;
+
+        let memory_struct = Memory {
+            id: String::new(),
+            content: memory.to_string(),
+            recency: 1.0,
+            recall: 1.0,
+            embedding: embedding.clone(),
+            metadata: metadata.clone()
+        };
+
+        self.upsert_memory(&memory_struct).await
+    }
+
     async fn get_memory_pool(
         &mut self,
         llm: &LLM,
         memory: &str,
         min_count: usize,
+        filter: &MemoryFilter
     ) -> Result<Vec<RelevantMemory>, Box<dyn Error>> {
-        let embedding = llm.model.get_base_embed(memory).await?;
-        let latest_point_id_option = self.latest_point_id.lock().await.clone();
-        let latest_point_id = latest_point_id_option.unwrap_or(0);
-
-        let mut points: Vec<PointId> = vec![];
-        let search_result;
-        if latest_point_id > 0 {
-            points.push(PointId {
-                point_id_options: Some(point_id::PointIdOptions::Num(latest_point_id)),
-            });
-
-            let recommend_request = RecommendPoints {
-                collection_name: self.collection_name.to_string(),
-                limit: min_count as u64,
-                with_payload: Some(WithPayloadSelector {
-                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
-                }),
-                params: None,
-                score_threshold: None,
-                offset: None,
-                with_vectors: None,
-                read_consistency: None,
-                positive: points,
-                negative: vec![],
-                filter: None,
-                using: None,
-                lookup_from: None
-            };
-
-            let recommend_response = self.client.recommend(&recommend_request).await?;
-            search_result = recommend_response.result;
-        } else {
-            let search_request = SearchPoints {
-                collection_name: self.collection_name.to_string(),
-                vector: embedding.clone(),
-                filter: None,
-                limit: min_count as u64,
-                with_payload: Some(WithPayloadSelector {
-                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
-                }),
-                params: None,
-                score_threshold: None,
-                offset: None,
-                vector_name: None,
-                with_vectors: None,
-                read_consistency: None
-            };
+        let embedding = llm.get_base_embed(memory).await?;
+        let decay = self.get_decay().await?;
+
+        let search_request = SearchPoints {
+            collection_name: self.collection_name.to_string(),
+            vector: embedding.clone(),
+            filter: get_qdrant_filter(filter),
+            limit: min_count as u64,
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+            }),
+            params: None,
+            score_threshold: None,
+            offset: None,
+            vector_name: None,
+            with_vectors: Some(true.into()),
+            read_consistency: None
+        };
 
-            let search_response = self.client.search_points(&search_request).await?;
-            search_result = search_response.result;
-        }
+        let search_result = self.client.search_points(&search_request).await?.result;
 
         let relevant_memories_result: Result<Vec<_>, _> = search_result
             .iter()
-            .map(|point| convert_to_relevant_memory(point))
+            .map(|point| convert_to_relevant_memory(point, &embedding, decay))
             .collect();
 
         match relevant_memories_result {
@@ -160,18 +191,107 @@ impl MemorySystem for QdrantMemorySystem {
         }
     }
 
-    async fn decay_recency(&mut self, _decay_factor: f32) -> Result<(), Box<dyn Error>> {
-        // TODO
+    async fn reinforce_recall(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let ids = ids.iter().map(|id| parse_point_id(id)).collect::<Vec<_>>();
+        let points = self.client.get_points(
+            self.collection_name.to_string(), &ids, Some(false), Some(true), None
+        ).await?.result;
+
+        let mut recalls = vec![];
+        for point in points {
+            let memory = convert_to_memory(point.id.as_ref(), &point.payload, None, 0.)?;
+            if let Some(id) = point.id {
+                recalls.push((id, memory.recall + 1.));
+            }
+        }
+
+        self.set_values("recall", recalls).await
+    }
+
+    /// Qdrant can't change a payload field by a factor, so rather than updating every point, memories
+    /// are decayed from the collection's total decay when they were stored, and only the total changes.
+    async fn decay_recency(&mut self, decay_factor: f32) -> Result<(), Box<dyn Error>> {
+        let mut payload = Payload::new();
+        payload.insert("decay", self.get_decay().await? + (decay_factor as f64).ln());
+
+        self.client.upsert_points(
+            self.decay_collection.to_string(),
+            vec![ PointStruct::new(0, vec![ 1. ], payload) ],
+            None
+        ).await?;
+
+        Ok(())
+    }
+
+    fn get_shared_store(&self) -> Option<String> {
+        Some(format!("qdrant {} {}", self.url, self.collection_name))
+    }
+
+    async fn get_all_memories(&mut self, filter: &MemoryFilter) -> Result<Vec<Memory>, Box<dyn Error>> {
+        let decay = self.get_decay().await?;
+        let mut memories = vec![];
+        let mut offset = None;
+
+        loop {
+            let scroll_request = ScrollPoints {
+                collection_name: self.collection_name.to_string(),
+                filter: get_qdrant_filter(filter),
+                offset,
+                limit: Some(256),
+                with_payload: Some(WithPayloadSelector {
+                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+                }),
+                with_vectors: Some(true.into()),
+                read_consistency: None
+            };
+
+            let scroll_response = self.client.scroll(&scroll_request).await?;
+            for point in &scroll_response.result {
+                memories.push(convert_to_memory(point.id.as_ref(), &point.payload, point.vectors.as_ref(), decay)?);
+            }
+
+            offset = scroll_response.next_page_offset;
+            if offset.is_none() {
+                break;
+            }
+        }
+
+        Ok(memories)
+    }
+
+    async fn delete_memories(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        let ids = ids.iter().map(|id| parse_point_id(id)).collect();
+        self.client.delete_points(self.collection_name.to_string(), &get_points_selector(ids), None).await?;
 
         Ok(())
     }
+
+    async fn import_memory(&mut self, memory: &Memory) -> Result<(), Box<dyn Error>> {
+        self.upsert_memory(memory).await
+    }
 }
 
 pub struct QdrantProvider;
 
 #[derive(Serialize, Deserialize)]
 pub struct QdrantMemoryConfig {
-    pub collection: String
+    pub collection: String,
+    /// Connects to `QDRANT_HOST`, or a local server, without a host or port.
+    #[serde(default)] pub host: Option<String>,
+    #[serde(default)] pub port: Option<u16>,
+    #[serde(rename = "api key", default)] pub api_key: Option<String>,
+    /// The size of the embeddings stored, 1536 by default. Only used when the collection is created.
+    #[serde(default)] pub dimensions: Option<usize>
+}
+
+impl QdrantMemoryConfig {
+    pub fn get_dimensions(&self) -> usize {
+        self.dimensions.unwrap_or(1536)
+    }
 }
 
 impl MemoryProvider for QdrantProvider {
@@ -185,23 +305,36 @@ impl MemoryProvider for QdrantProvider {
 
     fn create(&self, config: serde_json::Value) -> Result<Box<dyn MemorySystem>, Box<dyn Error>> {
         let rt = Runtime::new().expect("Failed to create Tokio runtime");
-        let client = rt.block_on(async {
-            init_qdrant_client().await
-        })?;
 
         let qdrant_config: QdrantMemoryConfig = serde_json::from_value(config)?;
+        let client = rt.block_on(async {
+            init_qdrant_client(&qdrant_config).await
+        })?;
+        let url = get_qdrant_url(&qdrant_config);
+        let dimensions = qdrant_config.get_dimensions();
         let collection_name = qdrant_config.collection;
+        let decay_collection = get_decay_collection(&collection_name);
 
         rt.block_on(async {
-            create_collection_if_not_exists(&client, &collection_name).await
+            create_collection_if_not_exists(&client, &collection_name, dimensions).await?;
+            create_collection_if_not_exists(&client, &decay_collection, 1).await
         })?;
 
         Ok(Box::new(QdrantMemorySystem { 
             client,
-            latest_point_id: Arc::new(Mutex::new(Some(0))),
-            collection_name: collection_name.to_string()
+            url,
+            collection_name: collection_name.to_string(),
+            decay_collection
         }))
     }
+
+    fn get_namespaced_config(&self, config: &serde_json::Value, namespace: &str) -> serde_json::Value {
+        let mut config = config.clone();
+        if let Some(collection) = config.get("collection").and_then(|el| el.as_str()) {
+            config["collection"] = format!("{collection}_{namespace}").into();
+        }
+        config
+    }
 }
 
 pub fn create_memory_qdrant() -> Box<dyn MemoryProvider> {
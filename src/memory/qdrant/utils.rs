@@ -1,29 +1,53 @@
-use std::error::Error;
+use std::{collections::HashMap, error::Error};
 
-use crate::{Memory, RelevantMemory, QdrantPayload};
+use crate::{Memory, RelevantMemory, QdrantPayload, QdrantMemoryConfig, MemoryFilter, compare_embeddings};
 
 use qdrant_client::prelude::*;
 use qdrant_client::qdrant::vectors::VectorsOptions;
+use qdrant_client::qdrant::point_id::PointIdOptions;
 use qdrant_client::qdrant::vectors_config::Config;
-use qdrant_client::qdrant::{CreateCollection, VectorParams, VectorsConfig, ScoredPoint};
-
-pub async fn init_qdrant_client() -> Result<QdrantClient, Box<dyn Error>> {
-    let qdrant_host = std::env::var("QDRANT_HOST")
-        .unwrap_or_else(|_| String::from("http://localhost:6334"));
+use qdrant_client::qdrant::r#match::MatchValue;
+use qdrant_client::qdrant::{CreateCollection, Distance, VectorParams, VectorsConfig, ScoredPoint, Filter, FieldCondition, Match, Range, PointId, Vectors};
+
+pub fn get_qdrant_url(qdrant_config: &QdrantMemoryConfig) -> String {
+    match (&qdrant_config.host, qdrant_config.port) {
+        (None, None) => std::env::var("QDRANT_HOST")
+            .unwrap_or_else(|_| String::from("http://localhost:6334")),
+        (host, port) => {
+            let host = host.as_deref().unwrap_or("localhost");
+            let host = if host.contains("://") { host.to_string() } else { format!("http://{host}") };
+            format!("{host}:{}", port.unwrap_or(6334))
+        }
+    }
+}
 
-    let config = QdrantClientConfig::from_url(&qdrant_host);
+pub async fn init_qdrant_client(qdrant_config: &QdrantMemoryConfig) -> Result<QdrantClient, Box<dyn Error>> {
+    let mut config = QdrantClientConfig::from_url(&get_qdrant_url(qdrant_config));
+    if let Some(api_key) = &qdrant_config.api_key {
+        config.set_api_key(api_key);
+    }
 
     let client = QdrantClient::new(Some(config))?;
 
     Ok(client)
 }
 
-pub async fn create_collection_if_not_exists(client: &QdrantClient, collection_name: &str) -> Result<(), Box<dyn Error>> {
+/// Creates a random (version 4) UUID for a point.
+pub fn create_point_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes.iter().map(|el| format!("{el:02x}")).collect::<String>();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+pub async fn create_collection_if_not_exists(client: &QdrantClient, collection_name: &str, dimensions: usize) -> Result<(), Box<dyn Error>> {
     let collection_exists = client.has_collection(collection_name.to_string()).await?;
 
     if !collection_exists {
         let collection_creation_result = client.create_collection(
-            &create_initial_collection(collection_name.to_string())
+            &create_initial_collection(collection_name.to_string(), dimensions)
         ).await;
         match collection_creation_result {
             Ok(_) => {},
@@ -42,14 +66,14 @@ pub async fn create_collection_if_not_exists(client: &QdrantClient, collection_n
     Ok(())
 }
 
-pub fn create_initial_collection(name: String) -> CreateCollection {
+pub fn create_initial_collection(name: String, dimensions: usize) -> CreateCollection {
     let mut create_collection = CreateCollection::default();
 
     create_collection.collection_name = name.to_string();
     create_collection.vectors_config = Some(VectorsConfig {
         config: Some(Config::Params(VectorParams {
-            size: 1536,
-            distance: 3,
+            size: dimensions as u64,
+            distance: Distance::Cosine as i32,
             ..Default::default()
         })),
         ..Default::default()
@@ -58,35 +82,102 @@ pub fn create_initial_collection(name: String) -> CreateCollection {
     return create_collection;
 }
 
-pub fn convert_to_relevant_memory(point: &ScoredPoint) -> Result<RelevantMemory, Box<dyn Error>> {
-    let json_string = serde_json::to_value(&point.payload).unwrap_or("".into());
+fn match_keyword(key: &str, value: &str) -> FieldCondition {
+    FieldCondition {
+        key: key.to_string(),
+        r#match: Some(Match {
+            match_value: Some(MatchValue::Keyword(value.to_string()))
+        }),
+        ..Default::default()
+    }
+}
 
-    let payload: QdrantPayload = match serde_json::from_value(json_string) {
-        Ok(p) => p,
-        Err(e) => {
-            return Err(Box::new(e));
-        }
+/// Pushes the filter down to Qdrant, so that only matching points are searched.
+pub fn get_qdrant_filter(filter: &MemoryFilter) -> Option<Filter> {
+    let keywords = [
+        ("namespace", &filter.namespace),
+        ("run", &filter.run),
+        ("task", &filter.task),
+        // Keywords match lists that contain them.
+        ("tools", &filter.tool),
+        ("urls", &filter.url)
+    ];
+
+    let mut conditions: Vec<FieldCondition> = keywords.into_iter()
+        .flat_map(|(key, value)| value.as_ref().map(|value| match_keyword(key, value)))
+        .collect();
+
+    if filter.after.is_some() || filter.before.is_some() {
+        conditions.push(FieldCondition {
+            key: "timestamp".to_string(),
+            range: Some(Range {
+                gte: filter.after.map(|el| el as f64),
+                lte: filter.before.map(|el| el as f64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+
+    if conditions.is_empty() {
+        return None;
+    }
+
+    Some(Filter {
+        must: conditions.into_iter().map(|el| el.into()).collect(),
+        ..Default::default()
+    })
+}
+
+/// `decay` is the collection's total decay, which the memory's recency is decayed to.
+pub fn convert_to_memory(id: Option<&PointId>, payload: &HashMap<String, Value>, vectors: Option<&Vectors>, decay: f64) -> Result<Memory, Box<dyn Error>> {
+    let payload: QdrantPayload = serde_json::from_value(serde_json::to_value(payload)?)?;
+
+    let embedding = match vectors.and_then(|vectors| vectors.vectors_options.as_ref()) {
+        Some(VectorsOptions::Vector(vector)) => vector.data.clone(),
+        _ => Vec::new()
     };
 
-    let point_embedding = match &point.vectors {
-        Some(vectors) => match &vectors.vectors_options {
-            Some(VectorsOptions::Vector(vector)) => vector.data.clone(),
-            _ => Vec::new(),
-        },
-        None => Vec::new(),
+    let id = match id.and_then(|id| id.point_id_options.as_ref()) {
+        Some(PointIdOptions::Num(id)) => id.to_string(),
+        Some(PointIdOptions::Uuid(id)) => id.clone(),
+        None => String::new()
     };
 
-    let memory = Memory {
+    Ok(Memory {
+        id,
         content: payload.content,
         recall: payload.recall,
-        recency: payload.recency,
-        embedding: point_embedding.clone()
-    };
-    let relevance = point.score;
+        recency: payload.recency * (decay - payload.decay).exp() as f32,
+        embedding,
+        metadata: payload.metadata
+    })
+}
 
+/// The point's relevance is its cosine similarity to the query, like the other memory systems, rather than
+/// its score, which is a dot product in collections created before they used cosine distance.
+pub fn convert_to_relevant_memory(point: &ScoredPoint, embedding: &[f32], decay: f64) -> Result<RelevantMemory, Box<dyn Error>> {
+    let memory = convert_to_memory(point.id.as_ref(), &point.payload, point.vectors.as_ref(), decay)?;
     Ok(RelevantMemory {
-        memory,
-        relevance,
+        relevance: compare_embeddings(embedding, &memory.embedding),
+        memory
     })
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::{MemoryMetadata, QdrantPayload};
+    use super::convert_to_memory;
+
+    #[test]
+    fn decays_recency_from_when_the_memory_was_stored() {
+        let payload = QdrantPayload::new("memory".to_string(), 1., 0.8, 0.5f64.ln(), MemoryMetadata::default());
+        let payload = payload.to_memory_map().unwrap();
+
+        let memory = convert_to_memory(None, &payload, None, 0.5f64.ln()).unwrap();
+        assert_eq!(memory.recency, 0.8);
+
+        let memory = convert_to_memory(None, &payload, None, 0.125f64.ln()).unwrap();
+        assert!((memory.recency - 0.2).abs() < 1e-6);
+    }
+}
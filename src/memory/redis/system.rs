@@ -1,21 +1,19 @@
 use std::cmp::Ordering;
-use std::{sync::Arc};
+use std::env;
 use std::error::Error;
 use redis::{Client};
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Runtime;
-use redis::Value::*;
 
-use crate::{LLM, Memory, MemoryProvider, RelevantMemory, MemorySystem, set_json_record, search_vector_field, create_index_if_not_exists};
-
-use tokio::{sync::Mutex};
+use crate::{LLM, Memory, MemoryProvider, RelevantMemory, MemorySystem, MemoryMetadata, MemoryFilter, compare_embeddings, set_json_record, search_vector_field, search_filter, search_keys, parse_search_results, create_index_if_not_exists};
 
 use async_trait::async_trait;
 
 #[derive(Serialize, Deserialize)]
 pub struct EmbeddedMemory {
     memory: RedisPayload,
-    embedding: Vec<f32>
+    embedding: Vec<f32>,
+    #[serde(default)] metadata: MemoryMetadata
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -27,15 +25,45 @@ pub struct RedisPayload {
 
 pub struct RedisMemorySystem {
     client: redis::Client,
-    latest_point_id: Arc<Mutex<u64>>,
+    url: String,
     index_name: String,
+    prefix: String
+}
+
+impl RedisMemorySystem {
+    async fn add_record(&self, embedded_memory: &EmbeddedMemory) -> Result<(), Box<dyn Error>> {
+        let mut con = self.client.get_tokio_connection().await?;
+
+        // The counter is kept in Redis, so IDs carry on from where the last run left off.
+        let id: u64 = redis::cmd("INCR")
+            .arg(format!("{}next_id", self.prefix))
+            .query_async(&mut con)
+            .await?;
+        let point_id = format!("{}{id}", self.prefix);
+
+        set_json_record(&mut con, &point_id, embedded_memory).await?;
+
+        Ok(())
+    }
+}
+
+impl EmbeddedMemory {
+    fn into_memory(self, id: String) -> Memory {
+        Memory {
+            id,
+            content: self.memory.content,
+            recall: self.memory.recall,
+            recency: self.memory.recency,
+            embedding: self.embedding,
+            metadata: self.metadata
+        }
+    }
 }
 
 #[async_trait]
 impl MemorySystem for RedisMemorySystem {
-    async fn store_memory(&mut self, llm: &LLM, memory: &str) -> Result<(), Box<dyn Error>> {
-        let embedding = llm.model.get_base_embed(memory).await?;
-        let mut con = self.client.get_tokio_connection().await?;
+    async fn store_memory(&mut self, llm: &LLM, memory: &str, metadata: &MemoryMetadata) -> Result<(), Box<dyn Error>> {
+        let embedding = llm.get_base_embed(memory).await?;
 
         let embedded_memory = EmbeddedMemory {
             memory: RedisPayload {
@@ -43,20 +71,15 @@ impl MemorySystem for RedisMemorySystem {
                 recency: 1.,
                 recall: 1.,
             },
-            embedding: embedding
+            embedding: embedding,
+            metadata: metadata.clone()
         };
 
-        let mut latest_point_id = self.latest_point_id.lock().await;
-        *latest_point_id += 1;
-        let point_id = latest_point_id.to_string();
-
-        set_json_record(&mut con, &point_id, &embedded_memory).await?;
-
-        Ok(())
+        self.add_record(&embedded_memory).await
     }
 
-    async fn get_memory_pool(&mut self, llm: &LLM, memory: &str, min_count: usize) -> Result<Vec<RelevantMemory>, Box<dyn Error>> {
-        let embedding = llm.model.get_base_embed(memory).await?;
+    async fn get_memory_pool(&mut self, llm: &LLM, memory: &str, min_count: usize, filter: &MemoryFilter) -> Result<Vec<RelevantMemory>, Box<dyn Error>> {
+        let embedding = llm.get_base_embed(memory).await?;
         let mut con = self.client.get_tokio_connection().await?;
 
         let query_blob: Vec<u8> = embedding
@@ -64,63 +87,156 @@ impl MemorySystem for RedisMemorySystem {
             .flat_map(|&value| value.to_le_bytes().to_vec())
             .collect();
 
-        let result: redis::Value = search_vector_field(&mut con, &self.index_name, &query_blob, min_count).await?;
-
-        let result_pairs: Vec<(String, f32)> = match result {
-            Bulk(items) => {
-                items
-                    .chunks_exact(2)
-                    .filter_map(|chunk| match (chunk.get(0), chunk.get(1)) {
-                        (Some(Data(key)), Some(Data(value))) => {
-                            let score: f32 = String::from_utf8_lossy(value)
-                                .parse()
-                                .unwrap_or_default();
-                            Some((String::from_utf8_lossy(key).into_owned(), score))
-                        }
-                        _ => None,
-                    })
-                    .collect()
-            }
-            _ => vec![],
-        };
+        let result = search_vector_field(&mut con, &self.index_name, &query_blob, min_count, filter).await?;
 
         let mut relevant_memories = vec![];
-        for (key, similarity) in result_pairs {
-            let json_data: String = redis::cmd("JSON.GET")
-                .arg(&key)
-                .query_async(&mut con)
-                .await?;
+        for result in parse_search_results(result).1 {
+            let Some(json) = result.get_field("$") else {
+                continue;
+            };
 
-            let data: EmbeddedMemory = serde_json::from_value(serde_json::Value::String(json_data))?;
+            let data: EmbeddedMemory = serde_json::from_slice(json)?;
 
+            // The index only finds the closest memories. Their relevance is the cosine similarity, like
+            // the other memory systems, rather than the index's distance, so they're ranked the same way.
+            let relevance = compare_embeddings(&embedding, &data.embedding);
             relevant_memories.push(RelevantMemory {
-                memory: Memory {
-                    content: data.memory.content,
-                    recall: data.memory.recall,
-                    recency: data.memory.recency,
-                    embedding: data.embedding,
-                },
-                relevance: similarity,
+                memory: data.into_memory(result.key),
+                relevance
             });
         }
 
-        // Sort the relevant memories by relevance and return the top min_count memories
         relevant_memories.sort_by(|a, b| b.relevance.partial_cmp(&a.relevance).unwrap_or(Ordering::Equal));
-        Ok(relevant_memories.into_iter().take(min_count).collect())
+        Ok(relevant_memories)
+    }
+
+    async fn reinforce_recall(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut con = self.client.get_tokio_connection().await?;
+
+        let mut pipe = redis::pipe();
+        for id in ids {
+            pipe.cmd("JSON.NUMINCRBY").arg(id).arg("$.memory.recall").arg(1).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut con).await?;
+
+        Ok(())
     }
 
-    async fn decay_recency(&mut self, _decay_factor: f32) -> Result<(), Box<dyn Error>> {
-        // TODO
+    async fn decay_recency(&mut self, decay_factor: f32) -> Result<(), Box<dyn Error>> {
+        let mut con = self.client.get_tokio_connection().await?;
+
+        let mut offset = 0;
+        loop {
+            let keys = search_keys(&mut con, &self.index_name, offset, 256).await?;
+            if keys.is_empty() {
+                break;
+            }
+
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.cmd("JSON.NUMMULTBY").arg(key).arg("$.memory.recency").arg(decay_factor).ignore();
+            }
+            pipe.query_async::<_, ()>(&mut con).await?;
+
+            offset += keys.len();
+        }
 
         Ok(())
     }
+
+    fn get_shared_store(&self) -> Option<String> {
+        Some(format!("redis {} {}", self.url, self.index_name))
+    }
+
+    async fn get_all_memories(&mut self, filter: &MemoryFilter) -> Result<Vec<Memory>, Box<dyn Error>> {
+        let mut con = self.client.get_tokio_connection().await?;
+
+        let mut memories = vec![];
+        loop {
+            let result = search_filter(&mut con, &self.index_name, filter, memories.len(), 256).await?;
+            let (total, results) = parse_search_results(result);
+
+            let count = memories.len();
+            for result in results {
+                let Some(json) = result.get_field("$") else {
+                    continue;
+                };
+
+                let data: EmbeddedMemory = serde_json::from_slice(json)?;
+                memories.push(data.into_memory(result.key));
+            }
+
+            if memories.len() == count || memories.len() >= total {
+                break;
+            }
+        }
+
+        Ok(memories)
+    }
+
+    async fn delete_memories(&mut self, ids: &[String]) -> Result<(), Box<dyn Error>> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut con = self.client.get_tokio_connection().await?;
+        redis::cmd("DEL").arg(ids).query_async::<_, ()>(&mut con).await?;
+
+        Ok(())
+    }
+
+    async fn import_memory(&mut self, memory: &Memory) -> Result<(), Box<dyn Error>> {
+        self.add_record(&EmbeddedMemory {
+            memory: RedisPayload {
+                content: memory.content.clone(),
+                recall: memory.recall,
+                recency: memory.recency
+            },
+            embedding: memory.embedding.clone(),
+            metadata: memory.metadata.clone()
+        }).await
+    }
 }
 
 pub struct RedisProvider;
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorAlgorithm {
+    /// Compares the query with every memory, which is exact but slows down with many memories.
+    #[default] Flat,
+    /// An approximate graph index, which stays fast with many memories.
+    Hnsw
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct RedisMemoryConfig {
-    pub index: String
+    pub index: String,
+    /// Connects to `REDIS_URL`, or a local server, without a URL.
+    #[serde(default)] pub url: Option<String>,
+    /// Prepended to every key, `<index>:` by default.
+    #[serde(default)] pub prefix: Option<String>,
+    #[serde(default)] pub algorithm: VectorAlgorithm,
+    /// HNSW only: the edges each node has, and how many candidates are kept while building and searching.
+    #[serde(default)] pub m: Option<u32>,
+    #[serde(rename = "ef construction", default)] pub ef_construction: Option<u32>,
+    #[serde(rename = "ef runtime", default)] pub ef_runtime: Option<u32>,
+    /// The size of the embeddings stored, 1536 by default. Only used when the index is created.
+    #[serde(default)] pub dimensions: Option<usize>
+}
+
+impl RedisMemoryConfig {
+    pub fn get_prefix(&self) -> String {
+        self.prefix.clone().unwrap_or(format!("{}:", self.index))
+    }
+
+    pub fn get_dimensions(&self) -> usize {
+        self.dimensions.unwrap_or(1536)
+    }
 }
 
 impl MemoryProvider for RedisProvider {
@@ -133,16 +249,21 @@ impl MemoryProvider for RedisProvider {
     }
 
     fn create(&self, config: serde_json::Value) -> Result<Box<dyn MemorySystem> ,Box<dyn Error> > {
-        let client = Client::open("redis://127.0.0.1/")?;
+        let redis_config: RedisMemoryConfig = serde_json::from_value(config)?;
+
+        let url = redis_config.url.clone()
+            .or(env::var("REDIS_URL").ok())
+            .unwrap_or("redis://127.0.0.1/".to_string());
+        let client = Client::open(url.as_str())?;
 
         let rt = Runtime::new().expect("Failed to create Tokio runtime");
 
-        let qdrant_config: RedisMemoryConfig = serde_json::from_value(config)?;
-        let index_name = qdrant_config.index;
+        let index_name = redis_config.index.clone();
+        let prefix = redis_config.get_prefix();
 
         rt.block_on(async {
             let mut con = client.get_tokio_connection().await?;
-            match create_index_if_not_exists(&mut con, &index_name, "$.embedding", 1536).await {
+            match create_index_if_not_exists(&mut con, &redis_config, "$.embedding", redis_config.get_dimensions()).await {
                 Ok(()) => {Ok(())}
                 Err(err) => {
                     eprintln!("Failed to create vector index: {}", err);
@@ -153,12 +274,51 @@ impl MemoryProvider for RedisProvider {
 
         Ok(Box::new(RedisMemorySystem {
             client,
-            latest_point_id: Arc::new(Mutex::new(0)),
-            index_name: index_name.to_string(), // This could be configured differently depending on your needs
+            url,
+            index_name,
+            prefix
         }))
     }
+
+    fn get_namespaced_config(&self, config: &serde_json::Value, namespace: &str) -> serde_json::Value {
+        let mut config = config.clone();
+        if let Some(index) = config.get("index").and_then(|el| el.as_str()) {
+            config["index"] = format!("{index}_{namespace}").into();
+        }
+        // RediSearch indexes every key that starts with the prefix, so the namespace goes first,
+        // or the other index would find these memories too.
+        if let Some(prefix) = config.get("prefix").and_then(|el| el.as_str()) {
+            config["prefix"] = format!("{namespace}:{prefix}").into();
+        }
+        config
+    }
 }
 
 pub fn create_memory_redis() -> Box<dyn MemoryProvider> {
     Box::new(RedisProvider)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{MemoryProvider, RedisMemoryConfig};
+    use super::RedisProvider;
+
+    fn get_prefixes(config: serde_json::Value) -> (String, String) {
+        let namespaced = RedisProvider.get_namespaced_config(&config, "reflections");
+        let config: RedisMemoryConfig = serde_json::from_value(config).unwrap();
+        let namespaced: RedisMemoryConfig = serde_json::from_value(namespaced).unwrap();
+        (config.get_prefix(), namespaced.get_prefix())
+    }
+
+    #[test]
+    fn keeps_namespaced_keys_out_of_the_base_index() {
+        let (base, namespaced) = get_prefixes(json!({ "index": "memories" }));
+        assert_eq!(namespaced, "memories_reflections:");
+        assert!(!namespaced.starts_with(&base));
+
+        let (base, namespaced) = get_prefixes(json!({ "index": "memories", "prefix": "mem:" }));
+        assert_eq!(namespaced, "reflections:mem:");
+        assert!(!namespaced.starts_with(&base));
+    }
+}
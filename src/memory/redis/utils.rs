@@ -1,6 +1,5 @@
-use base64::{engine::general_purpose::STANDARD, Engine};
-use redis::{RedisError, RedisResult};
-use crate::{EmbeddedMemory};
+use redis::RedisResult;
+use crate::{EmbeddedMemory, MemoryFilter, RedisMemoryConfig, VectorAlgorithm};
 
 use std::{borrow::Borrow};
 
@@ -16,80 +15,324 @@ pub async fn execute_redis_tool<T: redis::FromRedisValue, S: Borrow<str>>(
     cmd.query_async(con).await
 }
 
-pub async fn create_index_if_not_exists(con: &mut redis::aio::Connection, index_name: &str, field_path: &str, dimension: usize) -> redis::RedisResult<()> {
-    let index_exists: bool = redis::cmd("FT.INFO")
+/// The attributes of the vector field, for the configured algorithm.
+fn get_vector_attributes(config: &RedisMemoryConfig, dimension: usize) -> Vec<String> {
+    let mut attributes = vec![
+        "TYPE".to_string(), "FLOAT32".to_string(),
+        "DIM".to_string(), dimension.to_string(),
+        "DISTANCE_METRIC".to_string(), "L2".to_string()
+    ];
+
+    if config.algorithm == VectorAlgorithm::Hnsw {
+        let params = [
+            ("M", config.m),
+            ("EF_CONSTRUCTION", config.ef_construction),
+            ("EF_RUNTIME", config.ef_runtime)
+        ];
+        for (name, value) in params {
+            if let Some(value) = value {
+                attributes.push(name.to_string());
+                attributes.push(value.to_string());
+            }
+        }
+    }
+
+    attributes
+}
+
+/// The metadata fields that memories are filtered on, by their path, name and type.
+const METADATA_FIELDS: [(&str, &str, &str); 6] = [
+    ("$.metadata.namespace", "namespace", "TAG"),
+    ("$.metadata.run", "run", "TAG"),
+    ("$.metadata.task", "task", "TAG"),
+    ("$.metadata.tools[*]", "tools", "TAG"),
+    ("$.metadata.urls[*]", "urls", "TAG"),
+    ("$.metadata.timestamp", "timestamp", "NUMERIC")
+];
+
+fn get_metadata_schema(path: &'static str, name: &'static str, kind: &'static str) -> Vec<&'static str> {
+    match kind {
+        // Tags are split on a separator that won't show up in them, so they're matched whole.
+        "TAG" => vec![ path, "AS", name, kind, "SEPARATOR", TAG_SEPARATOR ],
+        _ => vec![ path, "AS", name, kind ]
+    }
+}
+
+/// Lists the names of the fields in a FT.INFO reply.
+pub fn get_index_attributes(info: &redis::Value) -> Vec<String> {
+    let redis::Value::Bulk(items) = info else {
+        return vec![];
+    };
+
+    let as_string = |value: &redis::Value| match value {
+        redis::Value::Data(data) => Some(String::from_utf8_lossy(data).into_owned()),
+        redis::Value::Status(status) => Some(status.clone()),
+        _ => None
+    };
+
+    // Newer versions list `attributes`, with the name after `attribute`, and older ones list
+    // `fields`, with the name first.
+    let Some(attributes) = items.chunks_exact(2)
+        .find(|el| matches!(as_string(&el[0]).as_deref(), Some("attributes") | Some("fields")))
+        .map(|el| &el[1]) else {
+        return vec![];
+    };
+    let redis::Value::Bulk(attributes) = attributes else {
+        return vec![];
+    };
+
+    attributes.iter()
+        .filter_map(|attribute| {
+            let redis::Value::Bulk(attribute) = attribute else {
+                return None;
+            };
+
+            let name = attribute.iter()
+                .position(|el| as_string(el).as_deref() == Some("attribute"))
+                .and_then(|ind| attribute.get(ind + 1))
+                .or(attribute.first())?;
+            as_string(name)
+        })
+        .collect()
+}
+
+/// Creates the index, or adds the metadata fields to an index made before memories had them.
+pub async fn create_index_if_not_exists(con: &mut redis::aio::Connection, config: &RedisMemoryConfig, field_path: &str, dimension: usize) -> redis::RedisResult<()> {
+    let index_name = config.index.as_str();
+    let info: Option<redis::Value> = redis::cmd("FT.INFO")
         .arg(index_name)
         .query_async(con)
         .await
-        .map(|_: redis::Value| true)
+        .map(Some)
         .or_else(|err: redis::RedisError| {
-            if err.kind() == redis::ErrorKind::TypeError {
-                Ok(false)
+            if err.kind() == redis::ErrorKind::TypeError || err.to_string().to_lowercase().contains("unknown index") {
+                Ok(None)
             } else {
                 Err(err)
             }
         })?;
 
-    if !index_exists {
-        let _ = execute_redis_tool::<redis::Value, _>(
-            con,
-            "FT.CREATE",
-            &[
-                index_name,
-                "ON",
-                "JSON",
-                "SCHEMA",
-                field_path,
-                "as",
-                "vector",
-                "VECTOR",
-                "FLAT",
-                "6",
-                "TYPE",
-                "FLOAT32",
-                "DIM",
-                &dimension.to_string(),
-                "DISTANCE_METRIC",
-                "L2"
-            ],
-        ).await;
+    if let Some(info) = info {
+        let attributes = get_index_attributes(&info);
+        for (path, name, kind) in METADATA_FIELDS {
+            if attributes.iter().any(|el| el == name) {
+                continue;
+            }
+
+            let mut args = vec![ index_name, "SCHEMA", "ADD" ];
+            args.extend(get_metadata_schema(path, name, kind));
+            execute_redis_tool::<(), _>(con, "FT.ALTER", &args).await?;
+        }
+
+        return Ok(());
+    }
+
+    let prefix = config.get_prefix();
+    let algorithm = match config.algorithm {
+        VectorAlgorithm::Flat => "FLAT",
+        VectorAlgorithm::Hnsw => "HNSW"
+    };
+    let attributes = get_vector_attributes(config, dimension);
+    let attribute_count = attributes.len().to_string();
+
+    let mut args = vec![
+        index_name,
+        "ON",
+        "JSON",
+        "PREFIX",
+        "1",
+        &prefix,
+        "SCHEMA",
+        field_path,
+        "as",
+        "vector",
+        "VECTOR",
+        algorithm,
+        &attribute_count
+    ];
+    args.extend(attributes.iter().map(|el| el.as_str()));
+    for (path, name, kind) in METADATA_FIELDS {
+        args.extend(get_metadata_schema(path, name, kind));
+    }
+
+    execute_redis_tool::<(), _>(con, "FT.CREATE", &args).await
+}
+
+const TAG_SEPARATOR: &str = "\x1f";
+
+/// Escapes the characters RediSearch treats as syntax in a tag.
+fn escape_tag(tag: &str) -> String {
+    let mut escaped = String::new();
+    for char in tag.chars() {
+        if !char.is_alphanumeric() && char != '_' {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+/// Pushes the filter down to RediSearch, so that only matching memories are searched.
+pub fn get_filter_query(filter: &MemoryFilter) -> String {
+    let tags = [
+        ("namespace", &filter.namespace),
+        ("run", &filter.run),
+        ("task", &filter.task),
+        ("tools", &filter.tool),
+        ("urls", &filter.url)
+    ];
+
+    let mut clauses: Vec<String> = tags.into_iter()
+        .flat_map(|(field, value)| value.as_ref().map(|value| format!("@{field}:{{{}}}", escape_tag(value))))
+        .collect();
+
+    if filter.after.is_some() || filter.before.is_some() {
+        let after = filter.after.map(|el| el.to_string()).unwrap_or("-inf".to_string());
+        let before = filter.before.map(|el| el.to_string()).unwrap_or("+inf".to_string());
+        clauses.push(format!("@timestamp:[{after} {before}]"));
     }
 
-    Ok(())
+    if clauses.is_empty() {
+        "*".to_string()
+    } else {
+        format!("({})", clauses.join(" "))
+    }
 }
 
+/// Finds the `k` memories matching the filter that are closest to the query embedding, given as
+/// the raw bytes of its `f32`s.
 pub async fn search_vector_field(
     con: &mut redis::aio::Connection,
     index_name: &str,
     query_blob: &[u8],
     k: usize,
+    filter: &MemoryFilter
 ) -> RedisResult<redis::Value> {
-    // check if k can be formatted as a string to prevent panic
-    let k_str = k.to_string();
-    if k_str.is_empty() {
-        return Err(
-            RedisError::from((redis::ErrorKind::TypeError, "Invalid k value"))
-        );
+    let k = k.max(1);
+
+    redis::cmd("FT.SEARCH")
+        .arg(index_name)
+        .arg(format!("{}=>[KNN {k} @vector $BLOB]", get_filter_query(filter)))
+        .arg("PARAMS")
+        .arg(2)
+        .arg("BLOB")
+        .arg(query_blob)
+        // Only 10 results are returned without a limit.
+        .arg("LIMIT")
+        .arg(0)
+        .arg(k)
+        .arg("DIALECT")
+        .arg(2)
+        .query_async(con)
+        .await
+}
+
+/// A document found by FT.SEARCH, with its fields by name.
+pub struct SearchResult {
+    pub key: String,
+    pub fields: Vec<(String, Vec<u8>)>
+}
+
+impl SearchResult {
+    pub fn get_field(&self, name: &str) -> Option<&[u8]> {
+        self.fields.iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_slice())
     }
+}
+
+/// Parses a FT.SEARCH reply, which is the total count, then each key followed by its fields and their values.
+pub fn parse_search_results(result: redis::Value) -> (usize, Vec<SearchResult>) {
+    let redis::Value::Bulk(items) = result else {
+        return (0, vec![]);
+    };
+    let total = match items.first() {
+        Some(redis::Value::Int(total)) => *total as usize,
+        _ => 0
+    };
+
+    let results = items.iter()
+        .skip(1)
+        .collect::<Vec<_>>()
+        .chunks_exact(2)
+        .filter_map(|chunk| {
+            let (redis::Value::Data(key), redis::Value::Bulk(fields)) = (chunk[0], chunk[1]) else {
+                return None;
+            };
+
+            let fields = fields.chunks_exact(2)
+                .filter_map(|field| match (&field[0], &field[1]) {
+                    (redis::Value::Data(name), redis::Value::Data(value)) => {
+                        Some((String::from_utf8_lossy(name).into_owned(), value.clone()))
+                    },
+                    _ => None
+                })
+                .collect();
 
-    let query_blob_str = STANDARD.encode(query_blob);
-
-    Ok(
-        execute_redis_tool::<redis::Value, _>(
-            con,
-            "FT.SEARCH",
-            &[
-                index_name,
-                &format!("*=>[KNN {} @vec $BLOB]", k_str),
-                "PARAMS",
-                "2",
-                "BLOB",
-                &query_blob_str,
-                "DIALECT",
-                "2",
-            ],
-        ).await?
-    )
+            Some(SearchResult {
+                key: String::from_utf8_lossy(key).into_owned(),
+                fields
+            })
+        })
+        .collect();
+
+    (total, results)
+}
+
+/// Lists the memories matching the filter, `count` at a time.
+pub async fn search_filter(
+    con: &mut redis::aio::Connection,
+    index_name: &str,
+    filter: &MemoryFilter,
+    offset: usize,
+    count: usize
+) -> RedisResult<redis::Value> {
+    execute_redis_tool::<redis::Value, _>(
+        con,
+        "FT.SEARCH",
+        &[
+            index_name,
+            &get_filter_query(filter),
+            "LIMIT",
+            &offset.to_string(),
+            &count.to_string(),
+            "DIALECT",
+            "2",
+        ],
+    ).await
+}
+
+/// Lists the keys of every memory in the index, `count` at a time.
+pub async fn search_keys(
+    con: &mut redis::aio::Connection,
+    index_name: &str,
+    offset: usize,
+    count: usize
+) -> RedisResult<Vec<String>> {
+    let result = execute_redis_tool::<redis::Value, _>(
+        con,
+        "FT.SEARCH",
+        &[
+            index_name,
+            "*",
+            "NOCONTENT",
+            "LIMIT",
+            &offset.to_string(),
+            &count.to_string(),
+        ],
+    ).await?;
+
+    // Results are the total count, then each key.
+    let redis::Value::Bulk(items) = result else {
+        return Ok(vec![]);
+    };
+    Ok(items.iter()
+        .skip(1)
+        .filter_map(|item| match item {
+            redis::Value::Data(key) => Some(String::from_utf8_lossy(key).into_owned()),
+            _ => None
+        })
+        .collect())
 }
 
 pub async fn set_json_record(
@@ -106,4 +349,71 @@ pub async fn set_json_record(
             &serde_json::to_value(&embedded_memory)?.to_string(),
         ],
     ).await
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use redis::Value::{Bulk, Data, Int};
+
+    use super::*;
+
+    fn data(text: &str) -> redis::Value {
+        Data(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn parses_search_results() {
+        let reply = Bulk(vec![
+            Int(2),
+            data("memory:1"),
+            Bulk(vec![ data("__vector_score"), data("0.25"), data("$"), data("{}") ]),
+            data("memory:2"),
+            Bulk(vec![ data("$"), data("{}"), data("__vector_score"), data("1.5") ])
+        ]);
+
+        let (total, results) = parse_search_results(reply);
+        assert_eq!(total, 2);
+        assert_eq!(results.iter().map(|el| el.key.as_str()).collect::<Vec<_>>(), [ "memory:1", "memory:2" ]);
+        assert_eq!(results[0].get_field("__vector_score"), Some("0.25".as_bytes()));
+        assert_eq!(results[1].get_field("__vector_score"), Some("1.5".as_bytes()));
+        assert_eq!(results[1].get_field("$"), Some("{}".as_bytes()));
+    }
+
+    #[test]
+    fn parses_empty_search_results() {
+        let (total, results) = parse_search_results(Bulk(vec![ Int(0) ]));
+        assert_eq!(total, 0);
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn lists_index_attributes() {
+        let info = Bulk(vec![
+            data("index_name"), data("memories"),
+            data("attributes"), Bulk(vec![
+                Bulk(vec![ data("identifier"), data("$.embedding"), data("attribute"), data("vector"), data("type"), data("VECTOR") ]),
+                Bulk(vec![ data("identifier"), data("$.metadata.run"), data("attribute"), data("run"), data("type"), data("TAG") ])
+            ]),
+            data("num_docs"), data("3")
+        ]);
+        assert_eq!(get_index_attributes(&info), [ "vector", "run" ]);
+
+        let old_info = Bulk(vec![
+            data("fields"), Bulk(vec![
+                Bulk(vec![ data("vector"), data("type"), data("VECTOR") ])
+            ])
+        ]);
+        assert_eq!(get_index_attributes(&old_info), [ "vector" ]);
+    }
+
+    #[test]
+    fn builds_filter_queries() {
+        assert_eq!(get_filter_query(&MemoryFilter::default()), "*");
+
+        let filter = MemoryFilter {
+            namespace: Some("my-project".to_string()),
+            after: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(get_filter_query(&filter), r"(@namespace:{my\-project} @timestamp:[10 +inf])");
+    }
+}
@@ -26,7 +26,7 @@ impl<'a> Display for CommandNoArgError<'a> {
 
 impl<'a> Error for CommandNoArgError<'a> {}
 
-use crate::{LLM, ScriptValue, MemorySystem, AutoType};
+use crate::{LLM, ScriptValue, MemorySystem, MemoryMetadata, AutoType, StaticMode, ApprovalPolicy, RunState, Budget};
 
 #[async_trait]
 pub trait PluginData: Any + Send + Sync {
@@ -55,7 +55,13 @@ impl EndGoals {
 pub struct AgentInfo {
     pub llm: LLM,
     pub observations: Box<dyn MemorySystem>,
-    pub reflections: Box<dyn MemorySystem>
+    pub reflections: Box<dyn MemorySystem>,
+    /// How many observations the agent reflects on at once. It doesn't reflect without one.
+    pub reflect_every: Option<usize>,
+    /// The observations since the agent last reflected.
+    pub unreflected: Vec<String>,
+    /// Where the agent's next memories come from. Tools and URLs are cleared once memories are saved.
+    pub memory_metadata: MemoryMetadata
 }
 
 pub struct Agents {
@@ -66,6 +72,25 @@ pub struct Agents {
 }
 
 impl Agents {
+    /// Makes every agent's memories a cycle older. Agents can share a store, which is only decayed once.
+    pub fn decay_memories(&mut self, decay_factor: f32) -> Result<(), Box<dyn Error>> {
+        let mut decayed = vec![];
+        for agent in [ &mut self.static_agent, &mut self.planner, &mut self.dynamic, &mut self.fast ] {
+            for memory in [ &mut agent.observations, &mut agent.reflections ] {
+                if let Some(store) = memory.get_shared_store() {
+                    if decayed.contains(&store) {
+                        continue;
+                    }
+                    decayed.push(store);
+                }
+
+                memory.decay_recency_sync(decay_factor)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn same(init: impl Fn() -> Result<AgentInfo, Box<dyn Error>>) -> Result<Agents, Box<dyn Error>> {
         Ok(
             Agents {
@@ -83,6 +108,10 @@ pub struct CommandContext {
     pub agents: Agents,
     pub plugins: Vec<Plugin>,
     pub disabled_tools: Vec<String>,
+    pub static_mode: StaticMode,
+    pub approval: ApprovalPolicy,
+    pub run: RunState,
+    pub budget: Budget,
     pub assets: HashMap<String, String>
 }
 
@@ -100,7 +100,7 @@ pub async fn browse_urls(ctx: &mut CommandContext, args: ScriptValue) -> Result<
                 }.to_string();
         
                 for (ind, chunk) in chunks.iter().enumerate() {
-                    println!("<{url}> {} {} / {}", "Summarizing Chunk".green(), ind + 1, chunks.len());
+                    eprintln!("<{url}> {} {} / {}", "Summarizing Chunk".green(), ind + 1, chunks.len());
         
                     ctx.agents.fast.llm.message_history.clear();
         
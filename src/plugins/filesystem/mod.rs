@@ -162,7 +162,7 @@ pub fn create_filesystem() -> Plugin {
                     ToolArgument::new("lines", r#"[ "line 1", "line 2" ]"#)
                 ],
                 run: Box::new(FileWriteImpl),
-                tool_type: ToolType::Resource
+                tool_type: ToolType::Action { needs_permission: true }
             },
             Tool {
                 name: "file_append".to_string(),
@@ -172,7 +172,7 @@ pub fn create_filesystem() -> Plugin {
                     ToolArgument::new("lines", r#"[ "line 1", "line 2" ]"#)
                 ],
                 run: Box::new(FileAppendImpl),
-                tool_type: ToolType::Resource
+                tool_type: ToolType::Action { needs_permission: true }
             },
             Tool {
                 name: "file_list".to_string(),
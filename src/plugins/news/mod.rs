@@ -55,8 +55,8 @@ pub async fn ask_news(ctx: &mut CommandContext, query: &str) -> Result<String, B
         .collect::<Vec<_>>()
         .join("\n");
     
-    println!("mhm?: {json:?}");
-    println!("hehe: {text}");
+    eprintln!("mhm?: {json:?}");
+    eprintln!("hehe: {text}");
 
     Ok(text)
 }
@@ -0,0 +1,221 @@
+use std::{collections::HashMap, error::Error};
+use async_recursion::async_recursion;
+
+use crate::{CommandContext, CommandResult, Tool, ToolType, Expression, Primitive, Statement, Body, ScriptValue, GPTRunError, parse_gptscript};
+
+pub type Scope = HashMap<String, ScriptValue>;
+pub type AllowCall<'a> = dyn FnMut(&ScriptCall) -> Result<(), Box<dyn Error>> + 'a;
+
+pub struct ScriptCall {
+    pub tool: String,
+    pub tool_type: ToolType,
+    pub args: ScriptValue
+}
+
+pub struct Interpreter<'a> {
+    pub context: &'a mut CommandContext,
+    pub scope: Scope,
+    pub out: Vec<String>,
+    pub allow_call: &'a mut AllowCall<'a>
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(
+        context: &'a mut CommandContext,
+        allow_call: &'a mut AllowCall<'a>
+    ) -> Self {
+        Self {
+            context,
+            scope: HashMap::new(),
+            out: vec![],
+            allow_call
+        }
+    }
+
+    #[async_recursion(?Send)]
+    pub async fn run_body(&mut self, body: &Body) -> Result<(), Box<dyn Error>> {
+        for statement in body {
+            self.run_statement(statement).await?;
+        }
+
+        Ok(())
+    }
+
+    #[async_recursion(?Send)]
+    pub async fn run_statement(&mut self, statement: &Statement) -> Result<(), Box<dyn Error>> {
+        match statement {
+            Statement::Expression(expr) => {
+                self.eval(expr).await?;
+            }
+            Statement::Assign(target, value) => {
+                let value = self.eval(value).await?;
+                self.assign(target, value)?;
+            }
+            Statement::For(target, iter, body) => {
+                let items = match self.eval(iter).await? {
+                    ScriptValue::List(list) => list,
+                    ScriptValue::Dict(dict) => dict.into_keys().map(|el| el.into()).collect(),
+                    other => {
+                        return Err(GPTRunError(format!("Cannot iterate over {:?}", other)).into());
+                    }
+                };
+
+                for item in items {
+                    self.assign(target, item)?;
+                    self.run_body(body).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn assign(&mut self, target: &Expression, value: ScriptValue) -> Result<(), Box<dyn Error>> {
+        match target {
+            Expression::Name(name) => {
+                self.scope.insert(name.clone(), value);
+                Ok(())
+            }
+            other => Err(Box::new(GPTRunError(format!("Cannot assign to {:?}", other))))
+        }
+    }
+
+    #[async_recursion(?Send)]
+    pub async fn eval(&mut self, expr: &Expression) -> Result<ScriptValue, Box<dyn Error>> {
+        match expr {
+            Expression::Primitive(primitive) => Ok(match primitive {
+                Primitive::String(string) => ScriptValue::String(string.clone()),
+                Primitive::Int(int) => ScriptValue::Int(*int),
+                Primitive::Float(float) => ScriptValue::Float(*float),
+                Primitive::Bool(bool) => ScriptValue::Bool(*bool),
+                Primitive::None => ScriptValue::None
+            }),
+            Expression::Name(name) => {
+                self.scope.get(name)
+                    .cloned()
+                    .ok_or(Box::new(GPTRunError(format!("No variable named '{name}'."))) as Box<dyn Error>)
+            }
+            Expression::List(list) => {
+                let mut items = vec![];
+                for item in list {
+                    items.push(self.eval(item).await?);
+                }
+                Ok(ScriptValue::List(items))
+            }
+            Expression::Dict(dict) => {
+                let mut items = HashMap::new();
+                for (key, value) in dict {
+                    items.insert(key.clone(), self.eval(value).await?);
+                }
+                Ok(ScriptValue::Dict(items))
+            }
+            Expression::GetAttr(value, attr) => {
+                let value = self.eval(value).await?;
+                let attr = self.eval(attr).await?;
+                get_attr(value, attr)
+            }
+            Expression::FunctionCall(name, args) => {
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.eval(arg).await?);
+                }
+                self.call(name, values).await
+            }
+        }
+    }
+
+    async fn call(&mut self, name: &str, args: Vec<ScriptValue>) -> Result<ScriptValue, Box<dyn Error>> {
+        let tool = self.context.plugins.iter()
+            .flat_map(|el| &el.tools)
+            .filter(|el| !self.context.disabled_tools.contains(&el.name))
+            .find(|el| el.name == name)
+            .map(|el| el.box_clone())
+            .ok_or(Box::new(GPTRunError(format!("No such tool named '{name}'."))) as Box<dyn Error>)?;
+
+        let args = to_tool_args(&tool, args)?;
+        (self.allow_call)(&ScriptCall {
+            tool: name.to_string(),
+            tool_type: tool.tool_type.clone(),
+            args: args.clone()
+        })?;
+
+        let result = match tool.run.invoke(self.context, args).await? {
+            CommandResult::Text(text) => ScriptValue::String(text),
+            CommandResult::ScriptValue(value) => value
+        };
+
+        let text = match &result {
+            ScriptValue::String(text) => text.clone(),
+            value => serde_yaml::to_string(value)
+                .map_err(|_| GPTRunError("Could not parse ScriptValue as YAML.".to_string()))?
+        };
+        self.out.push(format!("Tool use {name} returned:\n{text}"));
+
+        Ok(result)
+    }
+}
+
+/// Converts positional call arguments into the named arguments a tool expects.
+/// A single dictionary argument is passed through as-is.
+pub fn to_tool_args(tool: &Tool, args: Vec<ScriptValue>) -> Result<ScriptValue, Box<dyn Error>> {
+    if let [ ScriptValue::Dict(dict) ] = args.as_slice() {
+        let is_named = dict.keys().all(|key| tool.args.iter().any(|arg| &arg.name == key));
+        if is_named {
+            return Ok(ScriptValue::Dict(dict.clone()));
+        }
+    }
+
+    if args.len() > tool.args.len() {
+        return Err(Box::new(GPTRunError(format!(
+            "'{}' takes {} arguments, but was given {}.", tool.name, tool.args.len(), args.len()
+        ))));
+    }
+
+    Ok(ScriptValue::Dict(
+        tool.args.iter()
+            .zip(args)
+            .map(|(arg, value)| (arg.name.clone(), value))
+            .collect()
+    ))
+}
+
+pub fn get_attr(value: ScriptValue, attr: ScriptValue) -> Result<ScriptValue, Box<dyn Error>> {
+    match (value, attr) {
+        (ScriptValue::List(list), ScriptValue::Int(index)) => {
+            let len = list.len() as i64;
+            let index = if index < 0 { len + index } else { index };
+            if index < 0 || index >= len {
+                return Err(Box::new(GPTRunError(format!("Index {index} is out of range."))));
+            }
+            Ok(list[index as usize].clone())
+        }
+        (ScriptValue::Dict(dict), ScriptValue::String(key)) => {
+            dict.get(&key)
+                .cloned()
+                .ok_or(Box::new(GPTRunError(format!("No key named '{key}'."))) as Box<dyn Error>)
+        }
+        (value, attr) => Err(Box::new(GPTRunError(format!("Cannot index {:?} with {:?}", value, attr))))
+    }
+}
+
+pub async fn run_gptscript(
+    context: &mut CommandContext,
+    code: &str,
+    allow_call: &mut AllowCall<'_>
+) -> Result<(Scope, String), Box<dyn Error>> {
+    let body = parse_gptscript(code)?;
+
+    let mut interpreter = Interpreter::new(context, allow_call);
+    interpreter.run_body(&body).await?;
+
+    Ok((interpreter.scope, interpreter.out.join("\n\n")))
+}
+
+pub fn run_gptscript_sync(
+    context: &mut CommandContext,
+    code: &str,
+    allow_call: &mut AllowCall<'_>
+) -> Result<(Scope, String), Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(run_gptscript(context, code, allow_call))
+}
@@ -1,7 +1,9 @@
 mod parse;
 mod scriptvalue;
 mod convert;
+mod interpret;
 
 pub use parse::*;
 pub use scriptvalue::*;
-pub use convert::*;
\ No newline at end of file
+pub use convert::*;
+pub use interpret::*;
@@ -1,5 +1,5 @@
 use std::{error::Error, collections::HashMap, fmt::{Display, Debug}};
-use rustpython_parser::{parser::{parse_program}, ast::{StmtKind, ExprKind, Constant, Located}};
+use rustpython_parser::{parser::{parse_program}, ast::{StmtKind, ExprKind, Constant, Located, Unaryop}};
 use num_traits::ToPrimitive;
 
 #[derive(Debug)]
@@ -171,6 +171,13 @@ pub fn to_expr(node: ExprKind) -> Result<Expression, GPTParseError> {
                 _ => Err(GPTParseError(format!("Cannot parse constant {:?}", value)))
             }
         }
+        ExprKind::UnaryOp { op: Unaryop::USub, operand } => {
+            match to_expr(operand.node)? {
+                Expression::Primitive(Primitive::Int(int)) => Ok((-int).into()),
+                Expression::Primitive(Primitive::Float(float)) => Ok((-float).into()),
+                other => Err(GPTParseError(format!("Cannot negate {:?}", other)))
+            }
+        }
         ExprKind::Name { id, .. } => {
             Ok(Expression::Name(id.clone()))
         }
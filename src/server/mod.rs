@@ -0,0 +1,209 @@
+use std::{convert::Infallible, error::Error, net::SocketAddr};
+
+use hyper::{Body, Request, Response, Method, StatusCode, Server, body::Sender, service::{make_service_fn, service_fn}};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+
+use crate::{UpdateEvent, format_sse};
+
+mod runs;
+
+pub use runs::*;
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitTask {
+    pub task: String
+}
+
+pub fn json_response(status: StatusCode, value: impl Serialize) -> Response<Body> {
+    let body = serde_json::to_string(&value).unwrap_or("null".to_string());
+
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+pub fn error_response(status: StatusCode, error: &str) -> Response<Body> {
+    json_response(status, json!({ "error": error }))
+}
+
+pub fn get_query_param(req: &Request<Body>, name: &str) -> Option<String> {
+    req.uri().query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+async fn submit_task(store: &RunStore, req: Request<Body>) -> Result<Response<Body>, Box<dyn Error>> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let SubmitTask { task } = match serde_json::from_slice(&body) {
+        Ok(submitted) => submitted,
+        Err(err) => return Ok(error_response(StatusCode::BAD_REQUEST, &err.to_string()))
+    };
+
+    let id = store.start(task);
+
+    Ok(json_response(StatusCode::CREATED, json!({ "id": id })))
+}
+
+fn list_tasks(store: &RunStore) -> Response<Body> {
+    let runs = store.runs.lock().unwrap();
+    let runs = runs.iter()
+        .map(|(id, run)| json!({ "id": id, "task": run.task, "status": run.status }))
+        .collect::<Vec<_>>();
+
+    json_response(StatusCode::OK, runs)
+}
+
+fn with_run(store: &RunStore, id: &str, respond: impl FnOnce(&Run) -> Response<Body>) -> Response<Body> {
+    match store.runs.lock().unwrap().get(id) {
+        Some(run) => respond(run),
+        None => error_response(StatusCode::NOT_FOUND, &format!("no task with the id '{id}'"))
+    }
+}
+
+fn get_status(store: &RunStore, id: &str) -> Response<Body> {
+    with_run(store, id, |run| json_response(StatusCode::OK, json!({
+        "id": id,
+        "task": run.task,
+        "status": run.status,
+        "updates": run.updates.len(),
+        "error": run.error
+    })))
+}
+
+fn get_updates(store: &RunStore, id: &str, req: &Request<Body>) -> Response<Body> {
+    // Clients poll with the `next` value from their last response to only get new updates.
+    let since = get_query_param(req, "since")
+        .and_then(|since| since.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    with_run(store, id, |run| {
+        let since = since.min(run.updates.len());
+
+        json_response(StatusCode::OK, json!({
+            "status": run.status,
+            "updates": run.updates[since..],
+            "next": run.updates.len()
+        }))
+    })
+}
+
+async fn send_events(
+    store: RunStore, id: String, 
+    past_events: Vec<UpdateEvent>, receiver: Option<Receiver<UpdateEvent>>,
+    mut sender: Sender
+) -> Result<(), hyper::Error> {
+    for event in past_events {
+        let message = format_sse(&event).ok();
+        if let Some(message) = message {
+            sender.send_data(message.into()).await?;
+        }
+    }
+
+    if let Some(mut receiver) = receiver {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let message = format_sse(&event).ok();
+                    if let Some(message) = message {
+                        sender.send_data(message.into()).await?;
+                    }
+                },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break
+            }
+        }
+    }
+
+    let status = store.runs.lock().unwrap().get(&id).map(|run| run.status);
+    let message = format!("event: end\ndata: {}\n\n", json!({ "status": status }));
+    sender.send_data(message.into()).await
+}
+
+/// Streams a run's events as Server-Sent Events, starting after `Last-Event-ID` when reconnecting.
+fn stream_events(store: &RunStore, id: &str, req: &Request<Body>) -> Response<Body> {
+    let start = req.headers().get("Last-Event-ID")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.parse::<usize>().ok())
+        .map(|last_id| last_id + 1)
+        .unwrap_or(0);
+
+    let runs = store.runs.lock().unwrap();
+    let run = match runs.get(id) {
+        Some(run) => run,
+        None => return error_response(StatusCode::NOT_FOUND, &format!("no task with the id '{id}'"))
+    };
+
+    // Subscribing while holding the lock means no event is missed or sent twice.
+    let past_events = run.updates.iter()
+        .filter(|event| event.sequence >= start)
+        .cloned()
+        .collect::<Vec<_>>();
+    let receiver = run.sender.as_ref().map(|sender| sender.subscribe());
+    drop(runs);
+
+    let (sender, body) = Body::channel();
+    tokio::spawn(send_events(store.clone(), id.to_string(), past_events, receiver, sender));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+fn get_result(store: &RunStore, id: &str) -> Response<Body> {
+    with_run(store, id, |run| {
+        if run.status == RunStatus::Running {
+            return error_response(StatusCode::CONFLICT, "the task is still running");
+        }
+
+        json_response(StatusCode::OK, json!({
+            "status": run.status,
+            "response": run.response,
+            "error": run.error,
+            "assets": run.assets
+        }))
+    })
+}
+
+async fn handle(store: RunStore, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().trim_matches('/').to_string();
+    let path = path.split('/').collect::<Vec<_>>();
+
+    let response = match (req.method().clone(), path.as_slice()) {
+        (Method::POST, ["tasks"]) => submit_task(&store, req).await
+            .unwrap_or_else(|err| error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string())),
+        (Method::GET, ["tasks"]) => list_tasks(&store),
+        (Method::GET, ["tasks", id]) => get_status(&store, id),
+        (Method::GET, ["tasks", id, "updates"]) => get_updates(&store, id, &req),
+        (Method::GET, ["tasks", id, "events"]) => stream_events(&store, id, &req),
+        (Method::GET, ["tasks", id, "result"]) => get_result(&store, id),
+        _ => error_response(StatusCode::NOT_FOUND, "not found")
+    };
+
+    Ok(response)
+}
+
+/// Serves SmartGPT over HTTP. Every submitted task gets its own `SmartGPT`, loaded from `config`.
+pub async fn serve(config: String, port: u16) -> Result<(), Box<dyn Error>> {
+    let store = RunStore::new(config);
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    let make_service = make_service_fn(move |_| {
+        let store = store.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle(store.clone(), req)))
+        }
+    });
+
+    Server::bind(&addr).serve(make_service).await?;
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use std::{any::Any, collections::HashMap, error::Error, panic::{self, AssertUnwindSafe}, sync::{Arc, Mutex}, thread};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{load_config, Action, DisallowedAction, NotApprovedError, UpdateEvent, UpdateSink, UpdateEmitter, create_run_id};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum RunStatus {
+    #[serde(rename = "running")] Running,
+    #[serde(rename = "completed")] Completed,
+    #[serde(rename = "failed")] Failed
+}
+
+pub struct Run {
+    pub task: String,
+    pub status: RunStatus,
+    pub updates: Vec<UpdateEvent>,
+    /// Live events for streaming clients. Dropped once the run is over, which ends their streams.
+    pub sender: Option<broadcast::Sender<UpdateEvent>>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub assets: HashMap<String, String>
+}
+
+/// Records a run's events in the store and forwards them to any streaming clients.
+pub struct RunSink {
+    pub run_id: String,
+    pub runs: Arc<Mutex<HashMap<String, Run>>>
+}
+
+impl UpdateSink for RunSink {
+    fn send(&mut self, event: &UpdateEvent) -> Result<(), Box<dyn Error>> {
+        if let Some(run) = self.runs.lock().unwrap().get_mut(&self.run_id) {
+            run.updates.push(event.clone());
+
+            if let Some(sender) = &run.sender {
+                // Sending only fails when nobody is listening.
+                let _ = sender.send(event.clone());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct RunStore {
+    pub config: String,
+    pub runs: Arc<Mutex<HashMap<String, Run>>>
+}
+
+/// Gets the message a thread panicked with.
+fn get_panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|el| el.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// The server can't ask anyone for permission, so anything left to the user is denied.
+pub fn deny_unconfirmed(action: &Action) -> Result<(), DisallowedAction> {
+    Err(DisallowedAction(Box::new(NotApprovedError(
+        action.tool.clone(),
+        "it needs permission, and server runs are non-interactive".to_string()
+    ))))
+}
+
+impl RunStore {
+    pub fn new(config: String) -> Self {
+        Self {
+            config,
+            runs: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    /// Starts a task on its own thread with a fresh `SmartGPT`, returning the run's ID.
+    pub fn start(&self, task: String) -> String {
+        let id = create_run_id();
+
+        self.runs.lock().unwrap().insert(id.clone(), Run {
+            task: task.clone(),
+            status: RunStatus::Running,
+            updates: vec![],
+            sender: Some(broadcast::channel(1024).0),
+            response: None,
+            error: None,
+            assets: HashMap::new()
+        });
+
+        let config = self.config.clone();
+        let runs = self.runs.clone();
+        let run_id = id.clone();
+
+        // Agents block on their own runtimes, so each run needs a plain thread.
+        thread::spawn(move || {
+            // A panic still has to end the run, or it would be left running and its streams open.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(String, HashMap<String, String>), Box<dyn Error>> {
+                let (_, mut smartgpt) = load_config(&config)?;
+                smartgpt.context.lock().unwrap().run.id = run_id.clone();
+
+                let mut emitter = UpdateEmitter::new(&run_id, vec![
+                    Box::new(RunSink { run_id: run_id.clone(), runs: runs.clone() })
+                ]);
+
+                let response = smartgpt.run_task(&task, &mut deny_unconfirmed, &mut |update| emitter.emit(update))?;
+
+                let assets = smartgpt.context.lock().unwrap().assets.clone();
+                Ok((response, assets))
+            }));
+
+            let mut runs = runs.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(run) = runs.get_mut(&run_id) {
+                run.sender = None;
+
+                match result {
+                    Ok(Ok((response, assets))) => {
+                        run.status = RunStatus::Completed;
+                        run.response = Some(response);
+                        run.assets = assets;
+                    }
+                    Ok(Err(err)) => {
+                        run.status = RunStatus::Failed;
+                        run.error = Some(err.to_string());
+                    }
+                    Err(payload) => {
+                        run.status = RunStatus::Failed;
+                        run.error = Some(format!("the run panicked: {}", get_panic_message(payload.as_ref())));
+                    }
+                }
+            }
+        });
+
+        id
+    }
+}
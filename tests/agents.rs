@@ -0,0 +1,126 @@
+use std::error::Error;
+
+use smartgpt::*;
+
+/// Scripted responses for every prompt the agents send while carrying out a task with one subtask.
+/// The first matching pattern is used, and the prompt after a decision also mentions spawning agents.
+const RESPONSES: &str = r#"
+- pattern: previous request gave back
+  response: '{"thoughts": "t", "reasoning": "r", "decision": {"type": "final_response", "args": {"response": "Rust is a systems language."}}}'
+- pattern: concise plan
+  response: '{"concise plan on how you will complete the task": "Find facts, then answer."}'
+- pattern: spawning agents
+  response: '{"thoughts": "t", "reasoning": "r", "decision": {"type": "spawn_agent", "args": {"subtask": "find rust facts", "assets": [], "desired_response": "facts"}}}'
+- pattern: Create a list of steps
+  response: '{"thoughts": "t", "steps": [{"idea": "Brainstorm facts.", "decision": {"resource": {"name": "brainstorm", "question": "What is Rust?"}}}], "assets": [{"name": "facts", "description": "facts about rust"}]}'
+- pattern: carry out the next step
+  response: '{"thoughts": "t", "action": {"tool": "brainstorm", "args": {"lines": ["Rust has no garbage collector"]}}}'
+- pattern: write this asset
+  response: Rust is a systems language.
+- pattern: summarize all important
+  response: '{"actions": ["brainstormed"], "observations": ["Rust has no garbage collector"]}'
+"#;
+
+fn load_mock_config() -> SmartGPT {
+    let agent = format!("
+    llm:
+      mock:
+        responses: {}
+    memory:
+      local: {{}}
+    reflect every: null", serde_json::to_string(&serde_yaml::from_str::<serde_json::Value>(RESPONSES).unwrap()).unwrap());
+
+    let config = format!("
+type: runner
+task: Write about Rust.
+personality: A superintelligent AI.
+static mode: steps
+disabled tools: []
+approval:
+  mode: allow all
+budget:
+  max decisions: 5
+plugins:
+  brainstorm: {{}}
+agents:
+  dynamic: {agent}
+  static: {agent}
+  planner: {agent}
+  fast: {agent}
+");
+
+    load_config(&config).unwrap().1
+}
+
+fn collect_updates(updates: &mut Vec<Update>) -> impl FnMut(&Update) -> Result<(), Box<dyn Error>> + '_ {
+    |update| {
+        updates.push(update.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn brain_agent_reaches_final_response() {
+    let smartgpt = load_mock_config();
+    let mut context = smartgpt.context.lock().unwrap();
+
+    let mut updates = vec![];
+    let response = run_brain_agent(
+        &mut context,
+        &|ctx| &mut ctx.agents.dynamic,
+        "Write about Rust.",
+        "A superintelligent AI.",
+        &mut |_| Ok(()),
+        &mut collect_updates(&mut updates)
+    ).unwrap();
+
+    assert_eq!(response, "Rust is a systems language.");
+    assert_eq!(context.assets.get("facts").map(|el| el.as_str()), Some("Rust is a systems language."));
+    assert!(updates.iter().any(|el| matches!(el, Update::StaticAgent(StaticUpdate::AddedAsset(_)))));
+}
+
+#[test]
+fn method_agent_completes_its_plan() {
+    let smartgpt = load_mock_config();
+    let mut context = smartgpt.context.lock().unwrap();
+
+    let mut updates = vec![];
+    let response = run_method_agent(
+        &mut context,
+        &|ctx| &mut ctx.agents.static_agent,
+        &|ctx| &mut ctx.agents.planner,
+        "find rust facts",
+        "facts",
+        None,
+        "A superintelligent AI.",
+        &mut |_| Ok(()),
+        &mut collect_updates(&mut updates)
+    ).unwrap();
+
+    assert!(response.contains("## Asset `facts`\nRust is a systems language."));
+    assert!(updates.iter().any(|el| matches!(el, Update::StaticAgent(StaticUpdate::SelectedStep(_)))));
+
+    let observations = context.agents.static_agent.observations
+        .get_all_memories_sync(&MemoryFilter::default())
+        .unwrap();
+    assert_eq!(observations.iter().map(|el| el.content.as_str()).collect::<Vec<_>>(), [ "brainstormed", "Rust has no garbage collector" ]);
+}
+
+#[test]
+fn each_task_gets_its_own_run() {
+    let mut smartgpt = load_mock_config();
+    let first_run = smartgpt.context.lock().unwrap().run.id.clone();
+
+    let mut updates = vec![];
+    smartgpt.run_task("Write about Rust.", &mut |_| Ok(()), &mut collect_updates(&mut updates)).unwrap();
+    assert_eq!(smartgpt.context.lock().unwrap().run.id, first_run);
+    assert!(!updates.iter().any(|el| matches!(el, Update::NewRun(_))));
+
+    // Assistants start each task with a fresh history.
+    smartgpt.context.lock().unwrap().agents.dynamic.llm.clear_history();
+    let mut updates = vec![];
+    smartgpt.run_task("Write more about Rust.", &mut |_| Ok(()), &mut collect_updates(&mut updates)).unwrap();
+    let second_run = smartgpt.context.lock().unwrap().run.id.clone();
+    assert_ne!(second_run, first_run);
+    assert!(matches!(updates.first(), Some(Update::NewRun(run_id)) if *run_id == second_run));
+}